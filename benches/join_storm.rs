@@ -0,0 +1,48 @@
+//! Counts heap allocations while 1000 members join, with and without
+//! `expected_members` preallocation and the reused snapshot buffer. Run with
+//! `cargo bench --features bench`.
+
+extern crate swim;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use swim::bench::join_storm;
+
+const JOINS: usize = 1000;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    for &(expected_members, reuse_buffer) in &[(0, false), (JOINS + 1, false), (JOINS + 1, true)] {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let members = join_storm(expected_members, JOINS, reuse_buffer);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+        assert_eq!(members, JOINS + 1);
+
+        println!("expected_members {:>5}, reused snapshot buffer {:<5}: {:>9} allocations",
+                 expected_members, reuse_buffer, allocations);
+    }
+}
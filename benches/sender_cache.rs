@@ -0,0 +1,26 @@
+//! Compares sender lookups for a node receiving from many peers with and
+//! without the sender cache. Run with `cargo bench --features bench`.
+
+extern crate swim;
+
+use std::time::Instant;
+
+use swim::bench::receive_from_peers;
+
+const ROUNDS: usize = 20;
+
+fn main() {
+    for &peers in &[10, 100, 1000] {
+        for &cached in &[false, true] {
+            let started = Instant::now();
+            let known = receive_from_peers(peers, ROUNDS, cached);
+            let elapsed = started.elapsed();
+
+            assert_eq!(known, peers * ROUNDS);
+
+            let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+            println!("{:>5} peers, cache {:<5}: {:>8} ns per datagram",
+                     peers, cached, nanos / known as u64);
+        }
+    }
+}
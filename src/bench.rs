@@ -0,0 +1,66 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use member::{Member, MemberState};
+use memberlist::MemberList;
+use sender_cache::SenderCache;
+
+/// Receives `rounds` datagrams from each of `peers` known senders and checks
+/// whether each sender is a member the way `ensure_node_is_member` does,
+/// with or without the sender cache in front of the member list. Returns the
+/// number of datagrams from known senders.
+pub fn receive_from_peers(peers: usize, rounds: usize, cached: bool) -> usize {
+    let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+    let mut cache = SenderCache::new(if cached { peers } else { 0 });
+    let senders: Vec<(SocketAddr, Uuid)> = (0..peers)
+        .map(|i| (peer_addr(i), Uuid::new_v4()))
+        .collect();
+
+    for &(addr, host_key) in &senders {
+        members.add_member(Member::new(host_key, addr, 0, MemberState::Alive));
+    }
+
+    let mut known = 0;
+
+    for _ in 0..rounds {
+        for &(addr, host_key) in &senders {
+            if cache.contains(&addr, &host_key) {
+                known += 1;
+            }
+            else if members.has_member(&addr) || members.has_host_key(&host_key) {
+                cache.insert(addr, host_key);
+                known += 1;
+            }
+        }
+    }
+
+    known
+}
+
+/// Joins `joins` members into a member list sized for `expected_members`,
+/// taking a full state snapshot after every join like a Sync does. With
+/// `reuse_buffer` the snapshots fill one buffer of that size, otherwise each
+/// snapshot is a fresh vector. Returns the size of the last snapshot.
+pub fn join_storm(expected_members: usize, joins: usize, reuse_buffer: bool) -> usize {
+    let mut members = MemberList::with_capacity(Member::myself(Uuid::new_v4()), expected_members);
+    let mut buffer = Vec::with_capacity(expected_members);
+
+    for i in 0..joins {
+        members.add_member(Member::new(Uuid::new_v4(), peer_addr(i), 0, MemberState::Alive));
+
+        if reuse_buffer {
+            members.available_nodes_into(&mut buffer);
+        }
+        else {
+            buffer = members.available_nodes();
+        }
+    }
+
+    buffer.len()
+}
+
+fn peer_addr(index: usize) -> SocketAddr {
+    FromStr::from_str(&format!("10.0.{}.{}:2552", index / 250, index % 250 + 1)).unwrap()
+}
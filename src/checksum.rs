@@ -0,0 +1,79 @@
+pub const CHECKSUM_MARKER: u8 = 0x01;
+pub const CHECKSUM_OVERHEAD: usize = 5;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+pub fn append_checksum(payload: &[u8]) -> Vec<u8> {
+    let crc = crc32(payload);
+    let mut packet = Vec::with_capacity(payload.len() + CHECKSUM_OVERHEAD);
+
+    packet.push(CHECKSUM_MARKER);
+    packet.push((crc >> 24) as u8);
+    packet.push((crc >> 16) as u8);
+    packet.push((crc >> 8) as u8);
+    packet.push(crc as u8);
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+/// Returns the payload of a received packet, verifying its checksum if the
+/// packet carries one. Packets without the checksum marker are passed through
+/// unchanged so that nodes with and without checksums can coexist.
+pub fn strip_checksum(packet: &[u8]) -> Result<&[u8], ()> {
+    if packet.first() != Some(&CHECKSUM_MARKER) {
+        return Ok(packet);
+    }
+
+    if packet.len() < CHECKSUM_OVERHEAD {
+        return Err(());
+    }
+
+    let expected = ((packet[1] as u32) << 24)
+        | ((packet[2] as u32) << 16)
+        | ((packet[3] as u32) << 8)
+        | (packet[4] as u32);
+    let payload = &packet[CHECKSUM_OVERHEAD..];
+
+    if crc32(payload) == expected { Ok(payload) } else { Err(()) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{append_checksum, crc32, strip_checksum};
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_corrupted_packet_is_rejected() {
+        let mut packet = append_checksum(b"{\"sender\":\"abc\"}");
+
+        assert_eq!(strip_checksum(&packet), Ok(&b"{\"sender\":\"abc\"}"[..]));
+
+        let last = packet.len() - 1;
+        packet[last] ^= 0x01;
+
+        assert_eq!(strip_checksum(&packet), Err(()));
+    }
+
+    #[test]
+    fn test_unmarked_packet_passes_through() {
+        assert_eq!(strip_checksum(b"{}"), Ok(&b"{}"[..]));
+    }
+}
@@ -0,0 +1,65 @@
+use std::ops::{BitOr, BitOrAssign};
+
+use MemberEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventMask(u16);
+
+pub const MEMBER_JOINED: EventMask = EventMask(0b00000000001);
+pub const MEMBER_WENT_UP: EventMask = EventMask(0b00000000010);
+pub const MEMBER_SUSPECTED_DOWN: EventMask = EventMask(0b00000000100);
+pub const MEMBER_WENT_DOWN: EventMask = EventMask(0b00000001000);
+pub const MEMBER_LEFT: EventMask = EventMask(0b00000010000);
+pub const SUSPICION_CLEARED: EventMask = EventMask(0b00000100000);
+pub const JOINED: EventMask = EventMask(0b00001000000);
+pub const SOCKET_FAILED: EventMask = EventMask(0b00010000000);
+pub const EVENTS_SUMMARIZED: EventMask = EventMask(0b00100000000);
+pub const HEARTBEAT: EventMask = EventMask(0b01000000000);
+pub const ASYMMETRIC_PARTITION: EventMask = EventMask(0b10000000000);
+pub const ALL_EVENTS: EventMask = EventMask(0b11111111111);
+
+impl EventMask {
+    pub fn empty() -> EventMask {
+        EventMask(0)
+    }
+
+    pub fn contains(&self, other: EventMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn matches(&self, event: &MemberEvent) -> bool {
+        self.contains(EventMask::for_event(event))
+    }
+
+    pub fn for_event(event: &MemberEvent) -> EventMask {
+        use MemberEvent::*;
+
+        match *event {
+            MemberJoined(_) => MEMBER_JOINED,
+            MemberWentUp(_) => MEMBER_WENT_UP,
+            MemberSuspectedDown(_) => MEMBER_SUSPECTED_DOWN,
+            MemberWentDown(_) => MEMBER_WENT_DOWN,
+            MemberLeft(_) => MEMBER_LEFT,
+            SuspicionCleared(_) => SUSPICION_CLEARED,
+            Joined(_) => JOINED,
+            SocketFailed(_) => SOCKET_FAILED,
+            EventsSummarized(_, _) => EVENTS_SUMMARIZED,
+            Heartbeat(_, _) => HEARTBEAT,
+            AsymmetricPartition(_) => ASYMMETRIC_PARTITION,
+        }
+    }
+}
+
+impl BitOr for EventMask {
+    type Output = EventMask;
+
+    fn bitor(self, rhs: EventMask) -> EventMask {
+        EventMask(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for EventMask {
+    fn bitor_assign(&mut self, rhs: EventMask) {
+        self.0 |= rhs.0;
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(feature = "ed25519")]
+use std::convert::TryFrom;
+
+#[cfg(feature = "ed25519")]
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use member::Member;
+
+/// Signs the local member and verifies the members peers advertise.
+///
+/// A host key is bound to the public key in `ClusterConfig::trusted_identities`
+/// when that is set. Otherwise the first public key that verifies for a host
+/// key is pinned (trust on first use), so a peer holding the cluster key can
+/// still claim any host key this node has not seen yet.
+pub trait IdentitySigner: Send {
+    fn public_key(&self) -> Vec<u8>;
+    fn sign(&self, payload: &[u8]) -> Vec<u8>;
+    fn verify(&self, public_key: &[u8], payload: &[u8], signature: &[u8]) -> bool;
+}
+
+pub fn sign_member(signer: &IdentitySigner, member: &mut Member) {
+    member.set_identity(signer.public_key(), Vec::new());
+
+    let signature = signer.sign(&member.identity_payload());
+    let public_key = member.public_key().to_vec();
+    member.set_identity(public_key, signature);
+}
+
+pub fn verify_member(signer: &IdentitySigner,
+                     trusted: Option<&[u8]>,
+                     known: Option<&Member>,
+                     member: &Member) -> bool {
+    let pinned = match (trusted, known) {
+        (Some(trusted), _) => Some(trusted),
+        (None, Some(known)) if !known.public_key().is_empty() => Some(known.public_key()),
+        _ => None,
+    };
+
+    if let Some(pinned) = pinned {
+        if pinned != member.public_key() {
+            return false;
+        }
+    }
+
+    !member.public_key().is_empty()
+        && signer.verify(member.public_key(), &member.identity_payload(), member.signature())
+}
+
+/// Ed25519 signatures over the member identity, with 32 byte public keys.
+#[cfg(feature = "ed25519")]
+pub struct Ed25519Signer {
+    key: SigningKey,
+}
+
+#[cfg(feature = "ed25519")]
+impl Ed25519Signer {
+    pub fn from_secret_key(secret_key: &[u8; 32]) -> Ed25519Signer {
+        Ed25519Signer { key: SigningKey::from_bytes(secret_key) }
+    }
+}
+
+#[cfg(feature = "ed25519")]
+impl IdentitySigner for Ed25519Signer {
+    fn public_key(&self) -> Vec<u8> {
+        self.key.verifying_key().to_bytes().to_vec()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        self.key.sign(payload).to_bytes().to_vec()
+    }
+
+    fn verify(&self, public_key: &[u8], payload: &[u8], signature: &[u8]) -> bool {
+        let public_key = match VerifyingKey::try_from(public_key) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+
+        match Signature::from_slice(signature) {
+            Ok(signature) => public_key.verify(payload, &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use uuid::Uuid;
+
+    use member::{Member, MemberState};
+    use super::{sign_member, verify_member, IdentitySigner};
+
+    struct XorSigner(u8);
+
+    impl IdentitySigner for XorSigner {
+        fn public_key(&self) -> Vec<u8> {
+            vec![self.0]
+        }
+
+        fn sign(&self, payload: &[u8]) -> Vec<u8> {
+            payload.iter().map(|b| b ^ self.0).collect()
+        }
+
+        fn verify(&self, public_key: &[u8], payload: &[u8], signature: &[u8]) -> bool {
+            payload.iter().map(|b| b ^ public_key[0]).collect::<Vec<_>>() == signature
+        }
+    }
+
+    #[test]
+    fn test_signed_members_are_verified() {
+        let signer = XorSigner(0x5a);
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+
+        assert!(!verify_member(&signer, None, None, &member));
+
+        sign_member(&signer, &mut member);
+        assert!(verify_member(&signer, None, None, &member));
+
+        let mut spoofed = member.clone();
+        sign_member(&XorSigner(0x11), &mut spoofed);
+        assert!(verify_member(&signer, None, None, &spoofed));
+        assert!(!verify_member(&signer, None, Some(&member), &spoofed));
+
+        let forged = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+        let mut forged_with_stolen_signature = forged.clone();
+        forged_with_stolen_signature.set_identity(member.public_key().to_vec(), member.signature().to_vec());
+        assert!(!verify_member(&signer, None, None, &forged_with_stolen_signature));
+        assert!(!verify_member(&signer, None, Some(&member), &forged_with_stolen_signature));
+    }
+
+    #[test]
+    fn test_signature_covers_address_and_claimed_state() {
+        let signer = XorSigner(0x5a);
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+        sign_member(&signer, &mut member);
+
+        let mut moved = member.member_by_changing_host(FromStr::from_str("10.0.0.1:2552").unwrap());
+        assert!(!verify_member(&signer, None, Some(&member), &moved));
+
+        moved = member.clone();
+        moved.set_state(MemberState::Left);
+        assert!(!verify_member(&signer, None, Some(&member), &moved));
+
+        let mut suspected = member.clone();
+        suspected.set_state(MemberState::Suspect);
+        assert!(verify_member(&signer, None, Some(&member), &suspected));
+    }
+
+    #[test]
+    fn test_trusted_keys_reject_unseen_host_keys_claimed_with_other_keys() {
+        let signer = XorSigner(0x5a);
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+        sign_member(&XorSigner(0x11), &mut member);
+
+        assert!(verify_member(&signer, None, None, &member));
+        assert!(!verify_member(&signer, Some(&[0x5a][..]), None, &member));
+        assert!(verify_member(&signer, Some(&[0x11][..]), None, &member));
+    }
+
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn test_ed25519_signatures_bind_the_member_to_its_key() {
+        use super::Ed25519Signer;
+
+        let signer = Ed25519Signer::from_secret_key(&[7; 32]);
+        let other = Ed25519Signer::from_secret_key(&[9; 32]);
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+        sign_member(&signer, &mut member);
+
+        assert_eq!(member.public_key().len(), 32);
+        assert!(verify_member(&signer, Some(&signer.public_key()[..]), None, &member));
+        assert!(!verify_member(&signer, Some(&other.public_key()[..]), None, &member));
+
+        let mut moved = member.member_by_changing_host(FromStr::from_str("10.0.0.1:2552").unwrap());
+        moved.set_identity(member.public_key().to_vec(), member.signature().to_vec());
+        assert!(!verify_member(&signer, None, Some(&member), &moved));
+    }
+}
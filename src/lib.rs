@@ -3,14 +3,26 @@ extern crate time;
 extern crate uuid;
 extern crate rand;
 extern crate mio;
+extern crate net2;
+extern crate libc;
+extern crate hmac;
+extern crate sha2;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "ed25519")]
+extern crate ed25519_dalek;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::collections::hash_map::Entry;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 use std::default::Default;
+use std::error;
+use std::fmt;
+use std::io;
 use std::str::FromStr;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
+use std::time::Instant;
 
 use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 use rustc_serialize::json;
@@ -20,38 +32,185 @@ use uuid::Uuid;
 use mio::udp::UdpSocket;
 use mio::buf::MutBuf;
 
+use net2::{UdpBuilder, UdpSocketExt};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+#[cfg(feature = "bench")]
+pub mod bench;
+mod checksum;
+mod event_mask;
+mod identity;
 mod member;
 mod memberlist;
+mod rate_limit;
+mod replica;
+mod sender_cache;
+mod sink;
+mod stats;
+#[cfg(feature = "test-cluster")]
+pub mod testing;
+mod timeline;
+mod trace;
+mod view;
 
 use member::StateChange;
 use memberlist::MemberList;
+use rate_limit::TokenBucket;
+use sender_cache::SenderCache;
 
-pub use member::{Member, MemberState};
+pub use event_mask::{EventMask, MEMBER_JOINED, MEMBER_WENT_UP, MEMBER_SUSPECTED_DOWN, MEMBER_WENT_DOWN,
+                     MEMBER_LEFT, SUSPICION_CLEARED, JOINED, SOCKET_FAILED, EVENTS_SUMMARIZED,
+                     HEARTBEAT, ASYMMETRIC_PARTITION, ALL_EVENTS};
+pub use identity::IdentitySigner;
+#[cfg(feature = "ed25519")]
+pub use identity::Ed25519Signer;
+pub use member::{ConflictResolver, DefaultConflictResolver, LeftRejoinPolicy, Member, MemberState};
+pub use replica::Replica;
+pub use sink::{ChannelSink, EventSink};
+use replica::EventForwarder;
+pub use view::ViewSnapshot;
+pub use stats::{ClusterStats, DropReason};
+use stats::PacketCounters;
+pub use timeline::{TimelineEntry, TimelineEventKind};
+use timeline::Timeline;
+pub use trace::{MessageSummary, PacketDirection, PacketTrace, RequestKind};
 
 pub type ClusterEvent = (Vec<Member>, MemberEvent);
-type WaitList = HashMap<SocketAddr, Vec<SocketAddr>>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct SequencedEvent {
+    /// Increases by one for every event emitted by the cluster, so a jump
+    /// means events were missed
+    pub seq: u64,
+    pub members: Vec<Member>,
+    pub event: MemberEvent,
+}
+type WaitList = HashMap<SocketAddr, Vec<(time::Tm, SocketAddr)>>;
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 pub enum MemberEvent {
     MemberJoined(Member),
     MemberWentUp(Member),
     MemberSuspectedDown(Member),
     MemberWentDown(Member),
     MemberLeft(Member),
+    SuspicionCleared(Member),
+    Joined(Member),
+    SocketFailed(Member),
+    EventsSummarized(Member, Vec<Member>),
+    Heartbeat(Member, ViewSnapshot),
+    AsymmetricPartition(Member),
+}
+
+impl MemberEvent {
+    pub fn member(&self) -> &Member {
+        use MemberEvent::*;
+
+        match *self {
+            MemberJoined(ref m) | MemberWentUp(ref m) | MemberSuspectedDown(ref m)
+                | MemberWentDown(ref m) | MemberLeft(ref m) | SuspicionCleared(ref m)
+                | Joined(ref m) | SocketFailed(ref m) | EventsSummarized(ref m, _)
+                | Heartbeat(ref m, _) | AsymmetricPartition(ref m) => m,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Timeout,
+    AllSeedsRejected,
+    BindFailed(io::ErrorKind),
 }
 
 pub struct Cluster {
     pub events: Receiver<ClusterEvent>,
     comm: mio::Sender<InternalRequest>,
+    local_addr: SocketAddr,
+    stopped: bool,
+    shutdown_timeout: Duration,
+    exit_send_retries: usize,
 }
 
 pub struct ClusterConfig {
     pub cluster_key: Vec<u8>,
+    pub accepted_cluster_keys: Vec<Vec<u8>>,
     pub ping_interval: Duration,
     pub network_mtu: usize,
     pub ping_request_host_count: usize,
     pub ping_timeout: Duration,
+    pub seed_resolve_interval: Option<Duration>,
+    pub direct_ping_timeout: Option<Duration>,
     pub listen_addr: SocketAddr,
+    pub tags: HashSet<String>,
+    pub max_metadata_bytes: usize,
+    pub advertise_addr: Option<SocketAddr>,
+    pub reject_self_addressed_packets: bool,
+    pub conflict_resolver: Box<ConflictResolver>,
+    pub event_flush_interval: Option<Duration>,
+    pub event_priority: Box<Fn(&MemberEvent) -> u32 + Send>,
+    pub reuse_addr: bool,
+    pub reuse_port: bool,
+    pub dscp: Option<u8>,
+    pub auto_mtu: bool,
+    pub checksum: bool,
+    pub packet_tracer: Option<Box<Fn(PacketTrace) + Send>>,
+    pub timeline_size: usize,
+    pub assume_new_members_alive: bool,
+    pub emit_joined_event: bool,
+    pub self_state_hook: Option<Box<Fn(MemberState, MemberState) -> bool + Send>>,
+    pub suspicion_timeout: Duration,
+    pub adaptive_suspicion: bool,
+    pub suspicion_confirmations: usize,
+    pub new_member_grace: Duration,
+    pub wait_list_timeout: Duration,
+    pub max_wait_list_size: usize,
+    pub notify_failed_ping_requests: bool,
+    pub wire_overhead: usize,
+    pub log_full_membership: bool,
+    pub max_socket_failures: usize,
+    pub socket_rebind_attempts: usize,
+    pub max_suspicions_per_period: Option<usize>,
+    pub min_ping_size: usize,
+    pub receive_buffer_headroom: usize,
+    pub ack_window_size: usize,
+    pub suspicion_failure_ratio: f64,
+    pub prefer_low_rtt_relays: bool,
+    pub max_indirect_hops: u8,
+    pub relay_ack_window: Option<Duration>,
+    pub expected_members: usize,
+    pub identity: Option<Box<IdentitySigner>>,
+    pub shutdown_timeout: Duration,
+    pub exit_send_retries: usize,
+    pub ordered_state_changes: bool,
+    pub seed_tier_rounds: usize,
+    pub max_events_per_period: Option<usize>,
+    pub clock_skew_warning: Duration,
+    pub coalesce_state_changes: bool,
+    pub heartbeat_interval: Option<Duration>,
+    pub cluster_key_provider: Option<Box<Fn() -> Vec<u8> + Send>>,
+    pub cluster_key_grace_period: Duration,
+    pub asymmetry_window: Option<Duration>,
+    pub member_list_compaction: Option<f64>,
+    pub event_sink: Option<Box<EventSink>>,
+    pub member_history_size: usize,
+    pub initial_tick_delay: Option<Duration>,
+    pub multicast_discovery: Option<MulticastConfig>,
+    pub left_rejoin_policy: LeftRejoinPolicy,
+    pub max_send_bytes_per_sec: Option<usize>,
+    pub initial_incarnation: u64,
+    pub weighted_probe_selection: bool,
+    pub trusted_identities: Option<HashMap<Uuid, Vec<u8>>>,
+    pub member_reap_after: Option<Duration>,
+}
+
+/// Announces this node on a multicast group and treats announcements from
+/// other nodes with an accepted cluster key as seeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MulticastConfig {
+    pub group: SocketAddr,
+    pub announce_interval: Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -63,6 +222,13 @@ enum Request {
     Ack,
     PingRequest(EncSocketAddr),
     AckHost(Member),
+    PingRequestFailed(EncSocketAddr),
+    MtuProbe(usize, String),
+    MtuProbeAck(usize),
+    Reject,
+    Sync,
+    SyncAck,
+    PingRequestWithHops(EncSocketAddr, u8),
 }
 
 #[derive(Debug, Clone)]
@@ -71,461 +237,4550 @@ struct TargetedRequest {
     target: SocketAddr,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy)]
+enum Timer {
+    ProtocolPeriod,
+    DirectPingDeadline(SocketAddr),
+    Heartbeat,
+    Announce,
+}
+
 enum InternalRequest {
-    AddSeed(SocketAddr),
+    AddSeed(SocketAddr, u32),
+    AddSeedHost(String),
+    SeedHostsResolved(Vec<SocketAddr>),
+    Join(Vec<SocketAddr>, time::Tm, Sender<Result<(), JoinError>>),
+    ReplaceSeeds(Vec<SocketAddr>),
     Respond(SocketAddr, Message),
     React(TargetedRequest),
     LeaveCluster,
+    Flush,
+    PingMember(Uuid, time::Tm, Sender<bool>),
+    WaitForDeparture(Uuid, time::Tm, Sender<bool>),
+    IsJoined(Sender<bool>),
+    Timeline(Sender<Vec<TimelineEntry>>),
+    SetTags(HashSet<String>),
+    MembersWithTag(String, Sender<Vec<Member>>),
+    RecentTransitions(Uuid, Sender<Vec<(MemberState, Instant)>>),
+    MembersPage(usize, usize, Sender<(Vec<Member>, bool)>),
+    SetConfigEpoch(u64),
+    MinConfigEpoch(Sender<u64>),
+    EstimatedSize(Sender<usize>),
+    PendingChangeCount(Sender<usize>),
+    Incarnation(Sender<u64>),
+    EffectiveConfig(Sender<ClusterConfig>),
+    RotateClusterKey,
+    LoadMembers(Vec<Member>),
+    JoinedVia(Sender<Option<SocketAddr>>),
+    ClearSuspicion(Uuid),
+    ViewSnapshot(Sender<ViewSnapshot>),
+    SyncWith(SocketAddr),
+    MuteMember(Uuid, time::Tm),
+    ForEachMember(Box<FnMut(&Member) + Send>, Sender<()>),
+    MemberByAddr(SocketAddr, Sender<Option<Member>>),
+    Subscribe(EventMask, Sender<ClusterEvent>),
+    SubscribeSequenced(Sender<SequencedEvent>),
+    ForwardEvents(SocketAddr),
+    #[cfg(feature = "tokio")]
+    SubscribeAsync(tokio::sync::mpsc::Sender<ClusterEvent>),
+    #[cfg(feature = "chaos")]
+    SetChaos(f64),
+    Stats(Sender<ClusterStats>),
+    DropCounts(Sender<HashMap<DropReason, u64>>),
     Exit(Sender<()>),
 }
 
 struct State {
     host_key: Uuid,
     config: ClusterConfig,
+    cluster_key: Vec<u8>,
+    previous_cluster_key: Option<(Vec<u8>, time::Tm)>,
     members: MemberList,
-    seed_queue: Vec<SocketAddr>,
-    pending_responses: Vec<(time::Tm, SocketAddr, Vec<StateChange>)>,
+    seed_queue: Vec<(SocketAddr, u32)>,
+    seed_rounds: usize,
+    joined_via: Option<SocketAddr>,
+    monitored: Option<HashSet<SocketAddr>>,
+    seed_hosts: Vec<String>,
+    last_seed_resolve: time::Tm,
+    pending_responses: Vec<(time::Tm, SocketAddr, Vec<StateChange>, Instant)>,
     state_changes: Vec<StateChange>,
     wait_list: WaitList,
     server_socket: UdpSocket,
+    discovery_socket: Option<UdpSocket>,
+    local_addr: SocketAddr,
+    socket_failures: usize,
+    socket_unbound: bool,
+    send_budget: Option<TokenBucket>,
+    deferred_sends: VecDeque<TargetedRequest>,
     request_tx: mio::Sender<InternalRequest>,
-    event_tx: Sender<ClusterEvent>,
+    event_sink: Box<EventSink>,
+    pending_events: Vec<MemberEvent>,
+    queued_events: Vec<MemberEvent>,
+    last_event_flush: time::Tm,
+    effective_mtu: usize,
+    suspicion_sources: HashMap<Uuid, HashSet<SocketAddr>>,
+    ping_outcomes: HashMap<SocketAddr, VecDeque<bool>>,
+    rtt_estimates: HashMap<SocketAddr, Duration>,
+    message_sequence: u64,
+    muted_members: HashMap<Uuid, time::Tm>,
+    sender_cache: SenderCache,
+    period_event_count: usize,
+    summarized_members: Vec<Member>,
+    ping_observations: HashMap<(Uuid, Uuid), time::Tm>,
+    coalesced_changes: HashMap<Uuid, MemberState>,
+    state_change_sequences: HashMap<(SocketAddr, Uuid), (u64, u64)>,
+    subscribers: Vec<(EventMask, Sender<ClusterEvent>)>,
+    sequenced_subscribers: Vec<Sender<SequencedEvent>>,
+    event_seq: u64,
+    forwarders: Vec<EventForwarder>,
+    counters: PacketCounters,
+    timeline: Timeline,
+    ping_deadlines: Vec<SocketAddr>,
+    pending_forwards: Vec<(SocketAddr, SocketAddr, u8)>,
+    escalations_this_period: usize,
+    escalated_pings: HashSet<SocketAddr>,
+    ping_waiters: Vec<(Uuid, time::Tm, Sender<bool>)>,
+    departure_waiters: Vec<(Uuid, time::Tm, Sender<bool>)>,
+    joined: bool,
+    join_waiters: Vec<(Vec<SocketAddr>, time::Tm, Sender<Result<(), JoinError>>)>,
+    rejected_this_period: HashSet<SocketAddr>,
+    version_mismatches: HashSet<SocketAddr>,
+    ever_joined: bool,
+    snapshot_buffer: Vec<Member>,
+    cleared_suspicions: HashMap<Uuid, u64>,
+    refutation_claims: HashMap<SocketAddr, StateChange>,
+    sync_remainders: HashMap<SocketAddr, Vec<StateChange>>,
+    #[cfg(feature = "tokio")]
+    async_subscribers: Vec<tokio::sync::mpsc::Sender<ClusterEvent>>,
+    #[cfg(feature = "chaos")]
+    chaos_drop_rate: f64,
 }
 
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 struct Message {
+    protocol_version: Option<u8>,
     sender: Uuid,
     cluster_key: Vec<u8>,
+    sequence: Option<u64>,
+    timestamp_ms: Option<i64>,
     request: Request,
     state_changes: Vec<StateChange>,
+    ping_observations: Option<Vec<(Uuid, Uuid)>>,
+    monitor: Option<bool>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+struct Announcement {
+    sender: Uuid,
+    addr: EncSocketAddr,
+    timestamp_ms: i64,
+    mac: Vec<u8>,
 }
 
+type HmacSha256 = Hmac<Sha256>;
+
+/// The wire protocol version this build speaks. Bump it with every change
+/// that older peers cannot decode.
+///
+/// * 1: the original format, which does not carry a version
+/// * 2: ping requests that carry a hop count
+pub const PROTOCOL_VERSION: u8 = 2;
+
+const UNVERSIONED_PROTOCOL: u8 = 1;
+
 const SERVER: mio::Token = mio::Token(0);
+const DISCOVERY: mio::Token = mio::Token(1);
+const MIN_NETWORK_MTU: usize = 256;
+const SENDER_CACHE_SIZE: usize = 64;
+const MAX_DEFERRED_SENDS: usize = 1024;
+const MAX_REJECTS_PER_PERIOD: usize = 8;
+const ANNOUNCEMENT_MAX_AGE_MS: i64 = 60_000;
+const EXIT_RETRY_DELAY_MS: u64 = 10;
+#[cfg(feature = "tokio")]
+const ASYNC_EVENT_CAPACITY: usize = 1024;
 
 pub fn start_cluster(host_key: Uuid, config: ClusterConfig) -> Cluster {
+    try_start_cluster(host_key, config).unwrap()
+}
+
+pub fn try_start_cluster(host_key: Uuid, config: ClusterConfig) -> io::Result<Cluster> {
+    let server_socket = try!(open_server_socket(&config, &config.listen_addr));
+
+    run_cluster(host_key, config, server_socket, None)
+}
+
+pub fn start_cluster_with_socket(host_key: Uuid, config: ClusterConfig, socket: std::net::UdpSocket) -> Cluster {
+    try_start_cluster_with_socket(host_key, config, socket).unwrap()
+}
+
+pub fn try_start_cluster_with_socket(host_key: Uuid,
+                                     config: ClusterConfig,
+                                     socket: std::net::UdpSocket) -> io::Result<Cluster> {
+    let local_addr = try!(socket.local_addr());
+    try!(socket.set_nonblocking(true));
+
+    let server_socket = from_std_socket(socket);
+
+    if let Some(dscp) = config.dscp {
+        try!(set_dscp(&server_socket, &local_addr, dscp));
+    }
+
+    run_cluster(host_key, config, server_socket, None)
+}
+
+fn run_cluster(host_key: Uuid,
+               config: ClusterConfig,
+               server_socket: UdpSocket,
+               monitored: Option<Vec<SocketAddr>>) -> io::Result<Cluster> {
     let (event_tx, event_rx) = channel();
 
-    let (mut event_loop, mut state) = State::new(host_key, config, event_tx);
+    let (mut event_loop, mut state) = try!(State::with_socket(host_key, config, event_tx, server_socket));
+
+    if let Some(addrs) = monitored {
+        state.monitor(addrs);
+    }
+
     let internal_tx = event_loop.channel();
+    let local_addr = state.local_addr;
+    let shutdown_timeout = state.config.shutdown_timeout;
+    let exit_send_retries = state.config.exit_send_retries;
 
     thread::spawn(move || {
         event_loop.run(&mut state).unwrap();
     });
 
-    Cluster { events: event_rx, comm: internal_tx }
+    Ok(Cluster {
+        events: event_rx,
+        comm: internal_tx,
+        local_addr: local_addr,
+        stopped: false,
+        shutdown_timeout: shutdown_timeout,
+        exit_send_retries: exit_send_retries,
+    })
 }
 
 impl Cluster {
-    pub fn add_seed_node(&self, addr: SocketAddr) {
-        self.comm.send(InternalRequest::AddSeed(addr)).unwrap();
+    /// Starts a node that only probes `addrs` and reports when they go up or
+    /// down. It never gossips membership and ignores nodes outside `addrs`.
+    pub fn monitor(addrs: Vec<SocketAddr>, config: ClusterConfig) -> io::Result<Cluster> {
+        let server_socket = try!(open_server_socket(&config, &config.listen_addr));
+
+        run_cluster(Uuid::new_v4(), config, server_socket, Some(addrs))
     }
 
-    pub fn leave_cluster(&self) {
-        self.comm.send(InternalRequest::LeaveCluster).unwrap();
+    /// Binds, starts the event loop and joins through `seeds`, giving up once
+    /// `deadline` has passed. The event loop is shut down if joining fails.
+    pub fn start_and_join(host_key: Uuid,
+                          config: ClusterConfig,
+                          seeds: Vec<SocketAddr>,
+                          deadline: Duration) -> Result<Cluster, JoinError> {
+        let started = time::now_utc();
+
+        let cluster = match try_start_cluster(host_key, config) {
+            Ok(cluster) => cluster,
+            Err(e) => {
+                println!("ERROR: Could not start the cluster: {}", e);
+                return Err(JoinError::BindFailed(e.kind()));
+            },
+        };
+
+        match cluster.join(seeds, deadline - (time::now_utc() - started)) {
+            Ok(()) => Ok(cluster),
+            Err(e) => {
+                cluster.shutdown();
+                Err(e)
+            },
+        }
     }
-}
 
-impl Drop for Cluster {
-    fn drop(&mut self) {
-        let (tx, rx) = channel();
+    /// Stops the event loop and returns every event that was delivered to
+    /// `events` but not yet received, including those flushed during shutdown.
+    pub fn shutdown(mut self) -> Vec<ClusterEvent> {
+        self.stop();
+
+        let mut remaining = Vec::new();
 
-        self.comm.send(InternalRequest::Exit(tx)).unwrap();
+        while let Ok(event) = self.events.try_recv() {
+            remaining.push(event);
+        }
 
-        rx.recv().unwrap();
+        remaining
     }
-}
 
-impl mio::Handler for State {
-    type Timeout = ();
-    type Message = InternalRequest;
+    fn stop(&mut self) {
+        if self.stopped {
+            return;
+        }
 
-    fn ready(&mut self, _event_loop: &mut mio::EventLoop<Self>, token: mio::Token, events: mio::EventSet) {
-        if events.is_readable() && token == SERVER {
-            let mut data = vec![0; self.config.network_mtu];
-            let src_addr;
-            let remaining;
+        self.stopped = true;
 
-            {
-                let mut buf = mio::buf::MutSliceBuf::wrap(&mut data);
-                src_addr = self.server_socket.recv_from(&mut buf).unwrap();
-                remaining = buf.remaining();
+        let (tx, rx) = channel();
+        let mut request = InternalRequest::Exit(tx);
+        let mut retries = 0;
+
+        loop {
+            match self.comm.send(request) {
+                Ok(()) => break,
+                Err(mio::NotifyError::Full(returned)) if retries < self.exit_send_retries => {
+                    retries += 1;
+                    request = returned;
+                    thread::sleep(std::time::Duration::from_millis(EXIT_RETRY_DELAY_MS));
+                }
+                Err(mio::NotifyError::Full(_)) => {
+                    println!("WARNING: Could not ask the cluster event loop to exit, channel stayed full after {} retries",
+                             retries);
+                    return;
+                }
+                Err(_) => {
+                    println!("WARNING: Could not ask the cluster event loop to exit, it has likely stopped already");
+                    return;
+                }
             }
+        }
 
-            let size = self.config.network_mtu - remaining;
-            let message = json::decode(&*String::from_utf8_lossy(&data[..size]));
+        let acknowledged = match self.shutdown_timeout.to_std() {
+            Ok(timeout) => rx.recv_timeout(timeout).is_ok(),
+            Err(_) => false,
+        };
 
-            self.request_tx.send(InternalRequest::Respond(src_addr.unwrap(), message.unwrap())).unwrap();
+        if !acknowledged {
+            println!("WARNING: Cluster event loop did not acknowledge shutdown within {}", self.shutdown_timeout);
         }
     }
 
-    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Self>, _timeout: Self::Timeout) {
-        self.enqueue_seed_nodes();
-        self.enqueue_random_ping();
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
 
-        event_loop.timeout_ms((), self.config.ping_interval.num_milliseconds() as u64).unwrap();
+    pub fn protocol_version(&self) -> u8 {
+        PROTOCOL_VERSION
     }
 
-    fn notify(&mut self, event_loop: &mut mio::EventLoop<Self>, msg: InternalRequest) {
-        let exit_tx = self.process_internal_request(msg);
+    pub fn add_seed_node(&self, addr: SocketAddr) {
+        self.add_seed_node_with_priority(addr, 0);
+    }
 
-        if let Some(exit_tx) = exit_tx {
-            event_loop.shutdown();
-            exit_tx.send(()).unwrap();
-        }
+    pub fn add_seed_node_with_priority(&self, addr: SocketAddr, priority: u32) {
+        self.comm.send(InternalRequest::AddSeed(addr, priority)).unwrap();
     }
-}
 
-impl State {
-    fn new(host_key: Uuid,
-           config: ClusterConfig,
-           event_tx: Sender<ClusterEvent>) -> (mio::EventLoop<State>, State) {
-        let mut event_loop = mio::EventLoop::new().unwrap();
+    pub fn join(&self, seeds: Vec<SocketAddr>, timeout: Duration) -> Result<(), JoinError> {
+        let (tx, rx) = channel();
 
-        let server_socket = UdpSocket::bound(&config.listen_addr).unwrap();
+        self.comm.send(InternalRequest::Join(seeds, time::now_utc() + timeout, tx)).unwrap();
 
-        event_loop.register_opt(&server_socket, SERVER, mio::EventSet::all(), mio::PollOpt::edge()).unwrap();
+        match timeout.to_std() {
+            Ok(timeout) => rx.recv_timeout(timeout).unwrap_or(Err(JoinError::Timeout)),
+            Err(_) => Err(JoinError::Timeout),
+        }
+    }
 
-        let me = Member::myself(host_key.clone());
+    pub fn add_seed_host(&self, host: &str) {
+        self.comm.send(InternalRequest::AddSeedHost(String::from(host))).unwrap();
+    }
 
-        let state = State {
-            host_key: host_key,
-            config: config,
-            members: MemberList::new(me.clone()),
-            seed_queue: Vec::new(),
-            pending_responses: Vec::new(),
-            state_changes: vec![StateChange::new(me)],
-            wait_list: HashMap::new(),
-            server_socket: server_socket,
-            request_tx: event_loop.channel(),
-            event_tx: event_tx,
-        };
+    pub fn replace_seeds(&self, seeds: Vec<SocketAddr>) {
+        self.comm.send(InternalRequest::ReplaceSeeds(seeds)).unwrap();
+    }
 
-        event_loop.timeout_ms((), state.config.ping_interval.num_milliseconds() as u64).unwrap();
+    pub fn leave_cluster(&self) {
+        self.comm.send(InternalRequest::LeaveCluster).unwrap();
+    }
+
+    pub fn subscribe(&self, mask: EventMask) -> Receiver<ClusterEvent> {
+        let (tx, rx) = channel();
+
+        self.comm.send(InternalRequest::Subscribe(mask, tx)).unwrap();
 
-        (event_loop, state)
+        rx
     }
 
-    fn process_request(&mut self, request: TargetedRequest) {
-        use Request::*;
+    pub fn sequenced_events(&self) -> Receiver<SequencedEvent> {
+        let (tx, rx) = channel();
 
-        let timeout = time::now_utc() + self.config.ping_timeout;
-        let should_add_pending = request.request == Ping;
-        let message = build_message(&self.host_key,
-                                    &self.config.cluster_key,
-                                    request.request,
-                                    self.state_changes.clone(),
-                                    self.config.network_mtu);
+        self.comm.send(InternalRequest::SubscribeSequenced(tx)).unwrap();
 
-        if should_add_pending {
-            self.pending_responses.push((timeout, request.target.clone(), message.state_changes.clone()));
-        }
+        rx
+    }
 
-        let encoded = json::encode(&message).unwrap();
+    #[cfg(feature = "tokio")]
+    pub fn events_async(&self) -> tokio::sync::mpsc::Receiver<ClusterEvent> {
+        let (tx, rx) = tokio::sync::mpsc::channel(ASYNC_EVENT_CAPACITY);
 
-        assert!(encoded.len() < self.config.network_mtu);
+        self.comm.send(InternalRequest::SubscribeAsync(tx)).unwrap();
 
-        let mut buf = mio::buf::SliceBuf::wrap(encoded.as_bytes());
-        self.server_socket.send_to(&mut buf, &request.target).unwrap();
+        rx
     }
 
-    fn enqueue_seed_nodes(&self) {
-        for seed_node in &self.seed_queue {
-            self.request_tx.send(InternalRequest::React(TargetedRequest {
-                request: Request::Ping,
-                target: seed_node.clone(),
-            })).unwrap();
-        }
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&self, drop_rate: f64) {
+        self.comm.send(InternalRequest::SetChaos(drop_rate)).unwrap();
     }
 
-    fn enqueue_random_ping(&mut self) {
-        if let Some(member) = self.members.next_random_member() {
-            self.request_tx.send(InternalRequest::React(TargetedRequest {
-                request: Request::Ping,
-                target: member.remote_host().unwrap(),
-            })).unwrap();
-        }
+    pub fn set_tags(&self, tags: HashSet<String>) {
+        self.comm.send(InternalRequest::SetTags(tags)).unwrap();
     }
 
-    fn prune_timed_out_responses(&mut self) {
-        let now = time::now_utc();
+    pub fn members_with_tag(&self, tag: &str) -> Vec<Member> {
+        let (tx, rx) = channel();
 
-        let (remaining, expired): (Vec<_>, Vec<_>) = self.pending_responses
-            .iter()
-            .cloned()
-            .partition(| &(t, _, _) | t < now);
+        self.comm.send(InternalRequest::MembersWithTag(String::from(tag), tx)).unwrap();
 
-        let expired_hosts: HashSet<SocketAddr> = expired
-            .iter()
-            .map(| &(_, a, _) | a)
-            .collect();
+        rx.recv().unwrap()
+    }
 
-        self.pending_responses = remaining;
+    /// The most recent state transitions this node has seen for `host_key`,
+    /// oldest first. Empty unless `member_history_size` is set.
+    pub fn recent_transitions(&self, host_key: Uuid) -> Vec<(MemberState, Instant)> {
+        let (tx, rx) = channel();
 
-        let (suspect, down) = self.members.time_out_nodes(expired_hosts);
+        self.comm.send(InternalRequest::RecentTransitions(host_key, tx)).unwrap();
 
-        enqueue_state_change(&mut self.state_changes, &down);
-        enqueue_state_change(&mut self.state_changes, &suspect);
+        rx.recv().unwrap()
+    }
 
-        for member in suspect {
-            self.send_ping_requests(&member);
-            self.send_member_event(MemberEvent::MemberSuspectedDown(member.clone()));
-        }
+    pub fn members_paged(&self, offset: usize, limit: usize) -> (Vec<Member>, bool) {
+        let (tx, rx) = channel();
 
-        for member in down {
-            self.send_member_event(MemberEvent::MemberWentDown(member.clone()));
-        }
+        self.comm.send(InternalRequest::MembersPage(offset, limit, tx)).unwrap();
+
+        rx.recv().unwrap()
     }
 
-    fn send_ping_requests(&self, target: &Member) {
-        if let Some(target_host) = target.remote_host() {
-            for relay in self.members.hosts_for_indirect_ping(self.config.ping_request_host_count, &target_host) {
-                self.request_tx.send(InternalRequest::React(TargetedRequest {
-                    request: Request::PingRequest(EncSocketAddr::from_addr(&target_host)),
-                    target: relay,
-                })).unwrap();
-            }
-        }
+    pub fn set_config_epoch(&self, config_epoch: u64) {
+        self.comm.send(InternalRequest::SetConfigEpoch(config_epoch)).unwrap();
     }
 
-    fn process_internal_request(&mut self, message: InternalRequest) -> Option<Sender<()>> {
-        use InternalRequest::*;
+    pub fn min_config_epoch(&self) -> u64 {
+        let (tx, rx) = channel();
 
-        match message {
-            AddSeed(addr) => self.seed_queue.push(addr),
-            Respond(src_addr, message) => self.respond_to_message(src_addr, message),
-            React(request) => {
-                self.prune_timed_out_responses();
-                self.process_request(request);
-            },
-            LeaveCluster => {
-                let myself = self.members.leave();
-                enqueue_state_change(&mut self.state_changes, &[myself]);
-            },
-            Exit(tx) => return Some(tx),
-        };
+        self.comm.send(InternalRequest::MinConfigEpoch(tx)).unwrap();
 
-        None
+        rx.recv().unwrap()
     }
 
-    fn respond_to_message(&mut self, src_addr: SocketAddr, message: Message) {
-        use Request::*;
-
-        if message.cluster_key != self.config.cluster_key {
-            println!("ERROR: Mismatching cluster keys, ignoring message");
-        }
-        else {
-            self.apply_state_changes(message.state_changes, src_addr);
-            remove_potential_seed(&mut self.seed_queue, src_addr);
-
-            self.ensure_node_is_member(src_addr, message.sender);
+    pub fn member_by_addr(&self, addr: SocketAddr) -> Option<Member> {
+        let (tx, rx) = channel();
 
-            let response = match message.request {
-                Ping => Some(TargetedRequest { request: Ack, target: src_addr }),
-                Ack => {
-                    self.ack_response(src_addr);
-                    self.mark_node_alive(src_addr);
-                    None
-                },
-                PingRequest(dest_addr) => {
-                    let EncSocketAddr(dest_addr) = dest_addr;
-                    add_to_wait_list(&mut self.wait_list, &dest_addr, &src_addr);
-                    Some(TargetedRequest { request: Ping, target: dest_addr })
-                },
-                AckHost(member) => {
-                    self.ack_response(member.remote_host().unwrap());
-                    self.mark_node_alive(member.remote_host().unwrap());
-                    None
-                }
-            };
+        self.comm.send(InternalRequest::MemberByAddr(addr, tx)).unwrap();
 
-            match response {
-                Some(response) => self.request_tx.send(
-                    InternalRequest::React(response)).unwrap(),
-                None => (),
-            };
-        }
+        rx.recv().unwrap()
     }
 
-    fn ack_response(&mut self, src_addr: SocketAddr) {
-        let mut to_remove = Vec::new();
+    /// Runs `f` on the event loop thread for every available member without
+    /// cloning them, and returns once it has been called for all of them.
+    /// `f` must not block, since the protocol is stalled while it runs.
+    pub fn for_each_member<F>(&self, f: F) where F: FnMut(&Member) + Send + 'static {
+        let (tx, rx) = channel();
 
-        for &(ref t, ref addr, ref state_changes) in self.pending_responses.iter() {
-            if src_addr != *addr {
-                continue;
-            }
+        self.comm.send(InternalRequest::ForEachMember(Box::new(f), tx)).unwrap();
 
-            to_remove.push((t.clone(), addr.clone(), state_changes.clone()));
+        rx.recv().unwrap()
+    }
 
-            self.state_changes
-                .retain(|os| !state_changes.iter().any(| is | is.member().host_key() == os.member().host_key()))
-        }
+    pub fn mute_member(&self, host_key: Uuid, duration: Duration) {
+        self.comm.send(InternalRequest::MuteMember(host_key, time::now_utc() + duration)).unwrap();
+    }
 
-        self.pending_responses.retain(|op| !to_remove.iter().any(|ip| ip == op));
+    /// Sends this node's full state to `addr` and asks for its state in
+    /// return. States larger than one datagram are split across several.
+    pub fn sync_with(&self, addr: SocketAddr) {
+        self.comm.send(InternalRequest::SyncWith(addr)).unwrap();
     }
 
-    fn ensure_node_is_member(&mut self, src_addr: SocketAddr, sender: Uuid) {
-        if self.members.has_member(&src_addr) {
-            return;
-        }
+    pub fn view_snapshot(&self) -> ViewSnapshot {
+        let (tx, rx) = channel();
 
-        let new_member = Member::new(sender, src_addr, 0, MemberState::Alive);
+        self.comm.send(InternalRequest::ViewSnapshot(tx)).unwrap();
 
-        self.members.add_member(new_member.clone());
-        enqueue_state_change(&mut self.state_changes, &[new_member.clone()]);
-        self.send_member_event(MemberEvent::MemberJoined(new_member));
+        rx.recv().unwrap()
     }
 
-    fn send_member_event(&self, event: MemberEvent) {
-        use MemberEvent::*;
+    /// Marks a suspected member as alive and pings it with the suspicion, so
+    /// it refutes with a newer incarnation that wins on every node. Until
+    /// then, suspicions at the cleared incarnation are ignored here.
+    pub fn clear_suspicion(&self, host_key: Uuid) {
+        self.comm.send(InternalRequest::ClearSuspicion(host_key)).unwrap();
+    }
 
-        match event {
-            MemberJoined(_) => {},
-            MemberWentUp(ref m) => assert_eq!(m.state(), MemberState::Alive),
-            MemberWentDown(ref m) => assert_eq!(m.state(), MemberState::Down),
-            MemberSuspectedDown(ref m) => assert_eq!(m.state(), MemberState::Suspect),
-            MemberLeft(ref m) => assert_eq!(m.state(), MemberState::Left),
-        };
+    /// Returns the configuration the event loop is currently running with.
+    /// Cluster keys are redacted and callbacks are replaced by their defaults.
+    pub fn effective_config(&self) -> ClusterConfig {
+        let (tx, rx) = channel();
 
-        self.event_tx.send((self.members.available_nodes(), event)).unwrap();
+        self.comm.send(InternalRequest::EffectiveConfig(tx)).unwrap();
+
+        rx.recv().unwrap()
     }
 
-    fn apply_state_changes(&mut self, state_changes: Vec<StateChange>, from: SocketAddr) {
-        let (new, changed) = self.members.apply_state_changes(state_changes, &from);
+    /// Fetches the cluster key from `cluster_key_provider` again. Messages
+    /// using the previous key keep being accepted for `cluster_key_grace_period`.
+    pub fn rotate_cluster_key(&self) {
+        self.comm.send(InternalRequest::RotateClusterKey).unwrap();
+    }
+
+    /// Replaces every known member except this node with `members`, emitting
+    /// join and leave events for the difference. Additions and newer
+    /// incarnations are gossiped, but removals only affect this node: the
+    /// removed members have not actually left, so other nodes keep them.
+    pub fn load_members(&self, members: Vec<Member>) {
+        self.comm.send(InternalRequest::LoadMembers(members)).unwrap();
+    }
 
-        enqueue_state_change(&mut self.state_changes, &new);
-        enqueue_state_change(&mut self.state_changes, &changed);
+    pub fn joined_via(&self) -> Option<SocketAddr> {
+        let (tx, rx) = channel();
 
-        for member in new {
-            self.send_member_event(MemberEvent::MemberJoined(member));
-        }
+        self.comm.send(InternalRequest::JoinedVia(tx)).unwrap();
 
-        for member in changed {
-            self.send_member_event(determine_member_event(member));
-        }
+        rx.recv().unwrap()
     }
 
-    fn mark_node_alive(&mut self, src_addr: SocketAddr) {
-        if let Some(member) = self.members.mark_node_alive(&src_addr) {
-            match self.wait_list.get_mut(&src_addr) {
-                Some(mut wait_list) => {
-                    for remote in wait_list.iter() {
-                        self.request_tx.send(InternalRequest::React(TargetedRequest {
-                            request: Request::AckHost(member.clone()),
-                            target: *remote
-                        })).unwrap();
-                    }
+    pub fn estimated_size(&self) -> usize {
+        let (tx, rx) = channel();
 
-                    wait_list.clear();
-                },
-                None => ()
-            };
+        self.comm.send(InternalRequest::EstimatedSize(tx)).unwrap();
 
-            enqueue_state_change(&mut self.state_changes, &[member.clone()]);
-            self.send_member_event(MemberEvent::MemberWentUp(member.clone()));
-        }
+        rx.recv().unwrap()
     }
-}
 
-fn build_message(sender: &Uuid,
-                 cluster_key: &Vec<u8>,
-                 request: Request,
-                 state_changes: Vec<StateChange>,
-                 network_mtu: usize) -> Message {
-    let mut message = Message {
-        sender: sender.clone(),
-        cluster_key: cluster_key.clone(),
-        request: request.clone(),
-        state_changes: Vec::new(),
-    };
+    /// Current incarnation of this node. Storing it and passing it back as
+    /// `initial_incarnation` after a restart keeps the node from looking stale.
+    pub fn incarnation(&self) -> u64 {
+        let (tx, rx) = channel();
 
-    for i in 0..state_changes.len() + 1 {
-        message = Message {
-            sender: sender.clone(),
-            cluster_key: cluster_key.clone(),
-            request: request.clone(),
-            state_changes: (&state_changes[..i]).iter().cloned().collect(),
-        };
+        self.comm.send(InternalRequest::Incarnation(tx)).unwrap();
 
-        let encoded = json::encode(&message).unwrap();
-        if encoded.len() >= network_mtu {
-            return message;
-        }
+        rx.recv().unwrap()
     }
 
-    message
-}
+    /// Number of state changes still waiting to be gossiped. A value that keeps
+    /// growing means gossip isn't keeping up with membership churn.
+    pub fn pending_change_count(&self) -> usize {
+        let (tx, rx) = channel();
 
-fn add_to_wait_list(wait_list: &mut WaitList, wait_addr: &SocketAddr, notify_addr: &SocketAddr) {
-    match wait_list.entry(*wait_addr) {
-        Entry::Occupied(mut entry) => { entry.get_mut().push(notify_addr.clone()); },
-        Entry::Vacant(entry) => { entry.insert(vec![notify_addr.clone()]); }
-    };
-}
+        self.comm.send(InternalRequest::PendingChangeCount(tx)).unwrap();
 
-fn remove_potential_seed(seed_queue: &mut Vec<SocketAddr>, src_addr: SocketAddr) {
-    seed_queue.retain(|&addr| addr != src_addr)
-}
+        rx.recv().unwrap()
+    }
 
-fn determine_member_event(member: Member) -> MemberEvent {
-    use member::MemberState::*;
-    use MemberEvent::*;
+    pub fn ping(&self, host_key: Uuid, timeout: Duration) -> bool {
+        let (tx, rx) = channel();
+        let deadline = time::now_utc() + timeout;
 
-    match member.state() {
-        Alive => MemberWentUp(member),
-        Suspect => MemberSuspectedDown(member),
-        Down => MemberWentDown(member),
-        Left => MemberLeft(member),
+        self.comm.send(InternalRequest::PingMember(host_key, deadline, tx)).unwrap();
+
+        match timeout.to_std() {
+            Ok(timeout) => rx.recv_timeout(timeout).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    pub fn wait_for_departure(&self, host_key: Uuid, timeout: Duration) -> bool {
+        let (tx, rx) = channel();
+        let deadline = time::now_utc() + timeout;
+
+        self.comm.send(InternalRequest::WaitForDeparture(host_key, deadline, tx)).unwrap();
+
+        match timeout.to_std() {
+            Ok(timeout) => rx.recv_timeout(timeout).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    pub fn timeline(&self) -> Vec<TimelineEntry> {
+        let (tx, rx) = channel();
+
+        self.comm.send(InternalRequest::Timeline(tx)).unwrap();
+
+        rx.recv().unwrap()
+    }
+
+    pub fn is_joined(&self) -> bool {
+        let (tx, rx) = channel();
+
+        self.comm.send(InternalRequest::IsJoined(tx)).unwrap();
+
+        rx.recv().unwrap()
+    }
+
+    pub fn forward_events_to(&self, addr: SocketAddr) {
+        self.comm.send(InternalRequest::ForwardEvents(addr)).unwrap();
+    }
+
+    pub fn connect_replica(addr: SocketAddr) -> io::Result<Replica> {
+        Replica::listen(addr)
+    }
+
+    pub fn flush(&self) {
+        self.comm.send(InternalRequest::Flush).unwrap();
+    }
+
+    pub fn drop_counts(&self) -> HashMap<DropReason, u64> {
+        let (tx, rx) = channel();
+
+        self.comm.send(InternalRequest::DropCounts(tx)).unwrap();
+
+        rx.recv().unwrap()
+    }
+
+    pub fn stats(&self) -> ClusterStats {
+        let (tx, rx) = channel();
+
+        self.comm.send(InternalRequest::Stats(tx)).unwrap();
+
+        rx.recv().unwrap()
+    }
+
+    pub fn metrics_prometheus(&self) -> String {
+        self.stats().to_prometheus()
     }
 }
 
-fn enqueue_state_change(state_changes: &mut Vec<StateChange>, members: &[Member]) {
-    for member in members {
-        for state_change in state_changes.iter_mut() {
-            if state_change.member().host_key() == member.host_key() {
-                state_change.update(member.clone());
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl mio::Handler for State {
+    type Timeout = Timer;
+    type Message = InternalRequest;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Self>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() && token == SERVER {
+            let buffer_size = self.config.network_mtu + self.config.receive_buffer_headroom;
+            let mut data = vec![0; buffer_size];
+            let src_addr;
+            let remaining;
+
+            {
+                let mut buf = mio::buf::MutSliceBuf::wrap(&mut data);
+                src_addr = self.server_socket.recv_from(&mut buf);
+                remaining = buf.remaining();
+            }
+
+            let size = buffer_size - remaining;
+            let src_addr = match src_addr {
+                Ok(Some(src_addr)) => {
+                    self.socket_failures = 0;
+                    src_addr
+                },
+                Ok(None) => return,
+                Err(e) => {
+                    self.record_socket_error(e);
+                    self.recover_socket(event_loop);
+                    return;
+                },
+            };
+
+            let truncated = remaining == 0;
+
+            if truncated {
+                println!("WARNING: Packet from {} filled the {} byte receive buffer and was likely truncated",
+                         src_addr, buffer_size);
+            }
+
+            let advertise_addr = self.config.advertise_addr;
+
+            if self.config.reject_self_addressed_packets
+                && drop_self_addressed_packet(&self.local_addr, advertise_addr, &mut self.counters, &src_addr) {
+                return;
+            }
+
+            if self.chaos_drop() {
                 return;
             }
+
+            let payload = match checksum::strip_checksum(&data[..size]) {
+                Ok(payload) => payload,
+                Err(()) if truncated => {
+                    println!("WARNING: Truncated packet from {} failed its checksum, dropping it", src_addr);
+                    self.counters.record_dropped(DropReason::Oversized);
+                    return;
+                },
+                Err(()) => {
+                    println!("WARNING: Invalid checksum on packet from {}, dropping it", src_addr);
+                    self.counters.record_checksum_failure();
+                    return;
+                },
+            };
+
+            let message: Message = match json::decode(&*String::from_utf8_lossy(payload)) {
+                Ok(message) => message,
+                Err(e) => {
+                    let reason = if truncated { DropReason::Oversized } else { DropReason::DecodeFailure };
+
+                    println!("WARNING: Could not decode packet from {}, dropping it: {:?}", src_addr, e);
+                    self.counters.record_dropped(reason);
+                    return;
+                },
+            };
+
+            self.counters.record_received(size);
+            self.trace_packet(PacketDirection::Inbound, src_addr, size, &message);
+
+            self.send_internal(InternalRequest::Respond(src_addr, message));
         }
+        else if events.is_readable() && token == DISCOVERY {
+            self.receive_announcement();
+        }
+    }
 
-        state_changes.push(StateChange::new(member.clone()));
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Self>, timeout: Self::Timeout) {
+        match timeout {
+            Timer::ProtocolPeriod => {
+                self.recover_socket(event_loop);
+                self.rejected_this_period.clear();
+                self.escalations_this_period = 0;
+                self.members.expire_join_times(self.config.new_member_grace);
+                self.prune_join_waiters();
+                self.prune_departure_waiters();
+                self.prune_ping_waiters();
+                self.retry_deferred_sends();
+                self.expire_previous_cluster_key();
+
+                if let Some(interval) = self.config.seed_resolve_interval {
+                    if self.last_seed_resolve + interval <= time::now_utc() {
+                        let hosts = self.seed_hosts.clone();
+                        self.resolve_seed_hosts(hosts);
+                    }
+                }
+
+                self.enqueue_seed_nodes();
+                self.enqueue_random_ping();
+                self.prune_wait_list();
+
+                if let Some(interval) = self.config.event_flush_interval {
+                    if self.last_event_flush + interval <= time::now_utc() {
+                        self.flush_member_events();
+                    }
+                }
+
+                self.flush_coalesced_changes();
+                self.flush_event_summary();
+
+                if let Some(reap_after) = self.config.member_reap_after {
+                    for member in self.members.reap(reap_after) {
+                        self.sender_cache.invalidate(&member.host_key());
+                        self.suspicion_sources.remove(&member.host_key());
+                    }
+                }
+
+                if let Some(min_load_factor) = self.config.member_list_compaction {
+                    self.members.compact(min_load_factor, self.config.expected_members);
+                }
+
+                event_loop.timeout_ms(Timer::ProtocolPeriod, self.config.ping_interval.num_milliseconds() as u64).unwrap();
+            },
+            Timer::DirectPingDeadline(target) => self.escalate_unacked_ping(target),
+            Timer::Heartbeat => {
+                self.emit_heartbeat();
+
+                if let Some(interval) = self.config.heartbeat_interval {
+                    event_loop.timeout_ms(Timer::Heartbeat, interval.num_milliseconds() as u64).unwrap();
+                }
+            },
+            Timer::Announce => {
+                self.send_announcement();
+
+                if let Some(multicast) = self.config.multicast_discovery {
+                    event_loop.timeout_ms(Timer::Announce, multicast.announce_interval.num_milliseconds() as u64)
+                        .unwrap();
+                }
+            },
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Self>, msg: InternalRequest) {
+        let exit_tx = self.process_internal_request(msg);
+        self.recover_socket(event_loop);
+        self.dispatch_member_events();
+
+        if let Some(deadline) = self.config.direct_ping_timeout {
+            for target in self.ping_deadlines.drain(..) {
+                event_loop.timeout_ms(Timer::DirectPingDeadline(target), deadline.num_milliseconds() as u64).unwrap();
+            }
+        }
+
+        if let Some(exit_tx) = exit_tx {
+            self.flush_member_events();
+            event_loop.shutdown();
+            exit_tx.send(()).unwrap();
+        }
     }
 }
 
-impl Decodable for EncSocketAddr {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        match d.read_str() {
-            Ok(s) => match FromStr::from_str(&s) {
-                Ok(addr) => Ok(EncSocketAddr(addr)),
-                Err(e) => Err(d.error(&format!("{:?}", e))),
+impl State {
+    fn new(host_key: Uuid,
+           config: ClusterConfig,
+           event_tx: Sender<ClusterEvent>) -> io::Result<(mio::EventLoop<State>, State)> {
+        let server_socket = try!(open_server_socket(&config, &config.listen_addr));
+
+        State::with_socket(host_key, config, event_tx, server_socket)
+    }
+
+    fn with_socket(host_key: Uuid,
+                   mut config: ClusterConfig,
+                   event_tx: Sender<ClusterEvent>,
+                   server_socket: UdpSocket) -> io::Result<(mio::EventLoop<State>, State)> {
+        let mut event_loop = try!(mio::EventLoop::new());
+
+        let local_addr = try!(server_socket.local_addr());
+
+        if config.identity.is_some() && config.advertise_addr.is_none() {
+            if is_unspecified(&local_addr.ip()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "identity signing needs advertise_addr with a wildcard listen_addr"));
+            }
+
+            config.advertise_addr = Some(local_addr);
+        }
+
+        try!(event_loop.register_opt(&server_socket, SERVER, mio::EventSet::all(), mio::PollOpt::edge()));
+
+        let discovery_socket = match config.multicast_discovery {
+            Some(multicast) => {
+                let socket = try!(open_discovery_socket(&multicast.group));
+                try!(event_loop.register_opt(&socket, DISCOVERY, mio::EventSet::readable(), mio::PollOpt::edge()));
+                Some(socket)
             },
-            Err(e) => Err(e),
+            None => None,
+        };
+
+        let mut me = Member::myself(host_key.clone());
+        me.set_tags(config.tags.clone());
+        me.set_incarnation(config.initial_incarnation);
+        let effective_mtu = if config.auto_mtu { min_network_mtu(&config) } else { config.network_mtu };
+        let timeline = Timeline::new(config.timeline_size);
+        let expected_members = config.expected_members;
+        let mut members = MemberList::with_capacity(me.clone(), expected_members);
+        members.set_history_limit(config.member_history_size);
+        members.set_left_rejoin_policy(config.left_rejoin_policy);
+        members.set_weighted_selection(config.weighted_probe_selection);
+        let cluster_key = resolve_cluster_key(&config);
+        let send_budget = config.max_send_bytes_per_sec
+            .map(|rate| TokenBucket::new(rate, std::cmp::max(rate, config.network_mtu)));
+        let event_sink = config.event_sink
+            .take()
+            .unwrap_or_else(|| Box::new(ChannelSink::new(event_tx)) as Box<EventSink>);
+
+        let state = State {
+            host_key: host_key,
+            config: config,
+            cluster_key: cluster_key,
+            previous_cluster_key: None,
+            members: members,
+            seed_queue: Vec::new(),
+            seed_rounds: 0,
+            joined_via: None,
+            monitored: None,
+            seed_hosts: Vec::new(),
+            last_seed_resolve: time::now_utc(),
+            pending_responses: Vec::new(),
+            state_changes: vec![StateChange::new(me)],
+            wait_list: HashMap::new(),
+            server_socket: server_socket,
+            discovery_socket: discovery_socket,
+            local_addr: local_addr,
+            socket_failures: 0,
+            socket_unbound: false,
+            send_budget: send_budget,
+            deferred_sends: VecDeque::new(),
+            request_tx: event_loop.channel(),
+            event_sink: event_sink,
+            pending_events: Vec::new(),
+            queued_events: Vec::new(),
+            last_event_flush: time::now_utc(),
+            effective_mtu: effective_mtu,
+            suspicion_sources: HashMap::new(),
+            ping_outcomes: HashMap::new(),
+            rtt_estimates: HashMap::new(),
+            message_sequence: initial_message_sequence(),
+            muted_members: HashMap::new(),
+            sender_cache: SenderCache::new(SENDER_CACHE_SIZE),
+            period_event_count: 0,
+            summarized_members: Vec::new(),
+            ping_observations: HashMap::new(),
+            coalesced_changes: HashMap::new(),
+            state_change_sequences: HashMap::new(),
+            subscribers: Vec::new(),
+            sequenced_subscribers: Vec::new(),
+            event_seq: 0,
+            forwarders: Vec::new(),
+            counters: PacketCounters::default(),
+            timeline: timeline,
+            ping_deadlines: Vec::new(),
+            pending_forwards: Vec::new(),
+            escalations_this_period: 0,
+            escalated_pings: HashSet::new(),
+            ping_waiters: Vec::new(),
+            departure_waiters: Vec::new(),
+            joined: false,
+            join_waiters: Vec::new(),
+            rejected_this_period: HashSet::new(),
+            version_mismatches: HashSet::new(),
+            ever_joined: false,
+            snapshot_buffer: Vec::with_capacity(expected_members),
+            cleared_suspicions: HashMap::new(),
+            refutation_claims: HashMap::new(),
+            sync_remainders: HashMap::new(),
+            #[cfg(feature = "tokio")]
+            async_subscribers: Vec::new(),
+            #[cfg(feature = "chaos")]
+            chaos_drop_rate: 0.0,
+        };
+
+        let initial_delay = state.config.initial_tick_delay.unwrap_or(state.config.ping_interval);
+        event_loop.timeout_ms(Timer::ProtocolPeriod, initial_delay.num_milliseconds() as u64).unwrap();
+
+        if let Some(interval) = state.config.heartbeat_interval {
+            event_loop.timeout_ms(Timer::Heartbeat, interval.num_milliseconds() as u64).unwrap();
+        }
+
+        if state.discovery_socket.is_some() {
+            event_loop.timeout_ms(Timer::Announce, initial_delay.num_milliseconds() as u64).unwrap();
         }
+
+        Ok((event_loop, state))
     }
-}
 
-impl Encodable for EncSocketAddr {
-    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
-        let &EncSocketAddr(addr) = self;
-        format!("{}", addr).encode(e)
+    fn process_request(&mut self, request: TargetedRequest) {
+        self.send_request(request, false);
     }
-}
 
-impl EncSocketAddr {
-    fn from_addr(addr: &SocketAddr) -> Self {
-        EncSocketAddr(addr.clone())
+    fn send_request(&mut self, request: TargetedRequest, retried: bool) {
+        use Request::*;
+
+        let timeout = time::now_utc() + self.config.ping_timeout;
+        let should_add_pending = request.request == Ping;
+        let kind = request.request.kind();
+        let time_sensitive = request.request.is_time_sensitive();
+        let retry = if self.send_budget.is_some() { Some(request.clone()) } else { None };
+        let sequence = self.next_message_sequence();
+        let target = request.target;
+        let message = match request.request {
+            MtuProbe(size, _) => Some(build_probe_message(&self.host_key,
+                                                          &self.cluster_key,
+                                                          size,
+                                                          size - self.packet_overhead())),
+            Reject => build_message(&self.host_key,
+                                    &self.cluster_key,
+                                    sequence,
+                                    Reject,
+                                    Vec::new(),
+                                    self.effective_mtu - self.packet_overhead()),
+            request @ Sync | request @ SyncAck => {
+                // Whatever does not fit follows in SyncAck datagrams, which peers apply without replying
+                let state_changes = match self.sync_remainders.remove(&target) {
+                    Some(remainder) if request == SyncAck => remainder,
+                    _ => self.full_state_changes(),
+                };
+                let built = build_message_with_remainder(&self.host_key,
+                                                         &self.cluster_key,
+                                                         sequence,
+                                                         request,
+                                                         state_changes,
+                                                         self.effective_mtu - self.packet_overhead());
+
+                match built {
+                    Some((message, remainder)) => {
+                        if !remainder.is_empty() {
+                            self.sync_remainders.insert(target, remainder);
+                            self.send_internal(InternalRequest::React(TargetedRequest {
+                                request: SyncAck,
+                                target: target,
+                            }));
+                        }
+
+                        Some(message)
+                    },
+                    None => None,
+                }
+            },
+            request => {
+                let mut state_changes = self.outgoing_state_changes();
+
+                if let Some(claim) = self.refutation_claims.remove(&target) {
+                    state_changes.insert(0, claim);
+                }
+
+                build_message(&self.host_key,
+                              &self.cluster_key,
+                              sequence,
+                              request,
+                              state_changes,
+                              self.effective_mtu - self.packet_overhead())
+            },
+        };
+
+        let mut message = match message {
+            Some(message) => message,
+            None => {
+                println!("WARNING: {:?} request to {} does not fit in the network MTU, dropping it",
+                         kind, request.target);
+                return;
+            },
+        };
+
+        if self.config.asymmetry_window.is_some() {
+            let observations = recent_ping_observations(&self.ping_observations);
+            attach_ping_observations(&mut message, observations, self.effective_mtu - self.packet_overhead());
+        }
+
+        if self.monitored.is_some() {
+            message.monitor = Some(true);
+        }
+
+        let encoded = json::encode(&message).unwrap().into_bytes();
+        let encoded = if message.request == Ping {
+            pad_message(encoded, std::cmp::min(self.config.min_ping_size, self.effective_mtu - self.packet_overhead() - 1))
+        } else {
+            encoded
+        };
+        let encoded = if self.config.checksum { checksum::append_checksum(&encoded) } else { encoded };
+
+        assert!(encoded.len() + self.config.wire_overhead < self.config.network_mtu);
+
+        if let Some(retry) = retry {
+            if time_sensitive {
+                self.charge_send_budget(encoded.len());
+            }
+            else if !self.consume_send_budget(encoded.len()) {
+                self.defer_send(retry, retried);
+                return;
+            }
+        }
+
+        if should_add_pending {
+            self.pending_responses.push((timeout, request.target.clone(), message.state_changes.clone(), Instant::now()));
+            self.timeline.record(TimelineEventKind::PingSent, request.target);
+
+            if self.config.direct_ping_timeout.is_some() {
+                self.ping_deadlines.push(request.target);
+            }
+        }
+
+        self.trace_packet(PacketDirection::Outbound, request.target, encoded.len(), &message);
+
+        if self.chaos_drop() {
+            return;
+        }
+
+        let mut buf = mio::buf::SliceBuf::wrap(&encoded);
+
+        match self.server_socket.send_to(&mut buf, &request.target) {
+            Ok(_) => {
+                self.socket_failures = 0;
+                self.counters.record_sent(encoded.len());
+            },
+            Err(e) => self.record_socket_error(e),
+        }
     }
-}
 
-impl Default for ClusterConfig {
-    fn default() -> Self {
-        ClusterConfig {
-            cluster_key: "default".as_bytes().to_vec(),
-            ping_interval: Duration::seconds(1),
-            network_mtu: 512,
-            ping_request_host_count: 3,
-            ping_timeout: Duration::seconds(3),
-            listen_addr: "127.0.0.1:2552".to_socket_addrs().unwrap().next().unwrap(),
+    fn consume_send_budget(&mut self, size: usize) -> bool {
+        match self.send_budget {
+            Some(ref mut budget) => budget.try_consume(size),
+            None => true,
+        }
+    }
+
+    /// Acks and relayed probes are answered within the peer's ping timeout,
+    /// so they are charged to the budget instead of waiting for tokens.
+    fn charge_send_budget(&mut self, size: usize) {
+        if let Some(ref mut budget) = self.send_budget {
+            budget.consume(size);
+        }
+    }
+
+    fn defer_send(&mut self, request: TargetedRequest, retried: bool) {
+        if !retried {
+            self.counters.record_rate_limited();
+        }
+
+        if self.deferred_sends.len() >= MAX_DEFERRED_SENDS {
+            println!("WARNING: Outbound rate cap exceeded, dropping {:?} request to {}",
+                     request.request.kind(), request.target);
+            return;
+        }
+
+        self.deferred_sends.push_back(request);
+    }
+
+    fn retry_deferred_sends(&mut self) {
+        let deferred: Vec<_> = self.deferred_sends.drain(..).collect();
+
+        for request in deferred {
+            self.send_request(request, true);
+        }
+    }
+
+    fn next_message_sequence(&mut self) -> u64 {
+        self.message_sequence += 1;
+        self.message_sequence
+    }
+
+    fn send_internal(&mut self, request: InternalRequest) {
+        if let Err(e) = self.request_tx.send(request) {
+            let reason = match e {
+                mio::NotifyError::Full(_) => String::from("channel is full"),
+                mio::NotifyError::Closed(_) => String::from("channel is closed"),
+                mio::NotifyError::Io(e) => format!("{}", e),
+            };
+
+            println!("WARNING: Dropping internal request, {}", reason);
+            self.counters.record_internal_request_dropped();
         }
     }
+
+    fn record_socket_error(&mut self, error: io::Error) {
+        println!("ERROR: Server socket operation failed: {}", error);
+        self.socket_failures += 1;
+    }
+
+    fn recover_socket(&mut self, event_loop: &mut mio::EventLoop<Self>) {
+        if !self.socket_unbound && self.socket_failures < self.config.max_socket_failures {
+            return;
+        }
+
+        let was_unbound = self.socket_unbound;
+        self.socket_failures = 0;
+
+        for attempt in 0..self.config.socket_rebind_attempts {
+            match self.rebind_server_socket(event_loop) {
+                Ok(()) => {
+                    println!("WARNING: Re-created server socket on {}", self.local_addr);
+                    return;
+                },
+                Err(e) => println!("ERROR: Re-binding server socket on {} failed (attempt {}): {}",
+                                   self.local_addr, attempt + 1, e),
+            }
+        }
+
+        if !was_unbound {
+            let myself = self.members.myself();
+            self.send_member_event(MemberEvent::SocketFailed(myself));
+        }
+    }
+
+    /// Binds and registers the replacement before giving up the current
+    /// socket. Only when the current socket holds the address is it released
+    /// first; if binding then fails, recovery is retried every protocol period.
+    fn rebind_server_socket(&mut self, event_loop: &mut mio::EventLoop<Self>) -> io::Result<()> {
+        let socket = match open_server_socket(&self.config, &self.local_addr) {
+            Ok(socket) => socket,
+            Err(ref e) if e.kind() == io::ErrorKind::AddrInUse && !self.socket_unbound => {
+                let placeholder = try!(unbound_socket(&self.local_addr));
+                let old_socket = std::mem::replace(&mut self.server_socket, placeholder);
+                let _ = event_loop.deregister(&old_socket);
+                drop(old_socket);
+                self.socket_unbound = true;
+
+                try!(open_server_socket(&self.config, &self.local_addr))
+            },
+            Err(e) => return Err(e),
+        };
+
+        try!(event_loop.register_opt(&socket, SERVER, mio::EventSet::all(), mio::PollOpt::edge()));
+
+        let old_socket = std::mem::replace(&mut self.server_socket, socket);
+        let _ = event_loop.deregister(&old_socket);
+        self.socket_unbound = false;
+
+        Ok(())
+    }
+
+    fn packet_overhead(&self) -> usize {
+        packet_overhead(&self.config)
+    }
+
+    #[cfg(feature = "chaos")]
+    fn chaos_drop(&mut self) -> bool {
+        if self.chaos_drop_rate > 0.0 && rand::random::<f64>() < self.chaos_drop_rate {
+            self.counters.record_chaos_dropped();
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    fn chaos_drop(&mut self) -> bool {
+        false
+    }
+
+    fn full_state_changes(&mut self) -> Vec<StateChange> {
+        self.members.available_nodes_into(&mut self.snapshot_buffer);
+        self.snapshot_buffer.sort_by_key(|m| m.is_remote());
+
+        let state_changes: Vec<_> = self.snapshot_buffer.drain(..).map(StateChange::new).collect();
+
+        current_self_state_changes(&state_changes, &self.signed_myself(), self.config.advertise_addr)
+    }
+
+    fn signed_myself(&self) -> Member {
+        let mut myself = match self.config.advertise_addr {
+            Some(advertise_addr) => self.members.myself().member_by_changing_host(advertise_addr),
+            None => self.members.myself(),
+        };
+
+        if let Some(ref identity) = self.config.identity {
+            identity::sign_member(&**identity, &mut myself);
+        }
+
+        myself
+    }
+
+    fn verify_identity(&self, member: &Member) -> bool {
+        match self.config.identity {
+            Some(ref identity) if member.host_key() != self.host_key => {
+                let trusted = match self.config.trusted_identities {
+                    Some(ref trusted) => match trusted.get(&member.host_key()) {
+                        Some(public_key) => Some(&public_key[..]),
+                        None => return false,
+                    },
+                    None => None,
+                };
+                let known = self.members.member_by_host_key(&member.host_key());
+
+                identity::verify_member(&**identity, trusted, known.as_ref(), member)
+            },
+            _ => true,
+        }
+    }
+
+    fn monitor(&mut self, addrs: Vec<SocketAddr>) {
+        for &addr in &addrs {
+            self.add_seed(addr, 0);
+        }
+
+        self.monitored = Some(addrs.into_iter().collect());
+    }
+
+    fn outgoing_state_changes(&self) -> Vec<StateChange> {
+        if self.monitored.is_some() {
+            return Vec::new();
+        }
+
+        current_self_state_changes(&self.state_changes, &self.signed_myself(), self.config.advertise_addr)
+    }
+
+    fn announce_myself(&mut self) {
+        let myself = self.members.myself();
+        enqueue_state_change(&mut self.state_changes, &[myself]);
+    }
+
+    fn trace_packet(&self, direction: PacketDirection, addr: SocketAddr, size: usize, message: &Message) {
+        if let Some(ref tracer) = self.config.packet_tracer {
+            let summary = message.summary();
+
+            tracer(PacketTrace {
+                direction: direction,
+                addr: addr,
+                size: size,
+                kind: summary.kind,
+                summary: summary,
+            });
+        }
+    }
+
+    fn add_seed(&mut self, addr: SocketAddr, priority: u32) {
+        if self.seed_queue.iter().any(|&(a, _)| a == addr) {
+            return;
+        }
+
+        self.seed_queue.push((addr, priority));
+        self.seed_rounds = 0;
+
+        if self.config.auto_mtu {
+            self.send_mtu_probes(addr);
+        }
+    }
+
+    fn send_announcement(&mut self) {
+        let group = match self.config.multicast_discovery {
+            Some(multicast) => multicast.group,
+            None => return,
+        };
+
+        let announcement = sign_announcement(&self.cluster_key,
+                                             self.host_key,
+                                             self.config.advertise_addr.unwrap_or(self.local_addr),
+                                             wall_clock_ms());
+
+        let encoded = json::encode(&announcement).unwrap();
+
+        if let Some(ref socket) = self.discovery_socket {
+            let mut buf = mio::buf::SliceBuf::wrap(encoded.as_bytes());
+
+            if let Err(e) = socket.send_to(&mut buf, &group) {
+                println!("WARNING: Could not announce on multicast group {}: {}", group, e);
+            }
+        }
+    }
+
+    fn receive_announcement(&mut self) {
+        loop {
+            let mut data = vec![0; self.config.network_mtu];
+            let src_addr;
+            let remaining;
+
+            {
+                let socket = match self.discovery_socket {
+                    Some(ref socket) => socket,
+                    None => return,
+                };
+
+                let mut buf = mio::buf::MutSliceBuf::wrap(&mut data);
+                src_addr = socket.recv_from(&mut buf);
+                remaining = buf.remaining();
+            }
+
+            let src_addr = match src_addr {
+                Ok(Some(src_addr)) => src_addr,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("WARNING: Could not receive on the multicast discovery socket: {}", e);
+                    return;
+                },
+            };
+
+            let size = data.len() - remaining;
+            let announcement: Announcement = match json::decode(&*String::from_utf8_lossy(&data[..size])) {
+                Ok(announcement) => announcement,
+                Err(_) => continue,
+            };
+
+            if announcement.sender == self.host_key
+                || !verify_announcement(&announcement, &self.accepted_cluster_keys(), wall_clock_ms()) {
+                continue;
+            }
+
+            let seed = announced_seed_addr(announcement.addr.0, src_addr);
+
+            if !self.members.available_nodes().iter().any(|m| m.remote_host() == Some(seed)) {
+                self.add_seed(seed, 0);
+            }
+        }
+    }
+
+    /// DNS lookups can block for seconds, so they run on a helper thread that
+    /// posts the addresses back to the event loop.
+    fn resolve_seed_hosts(&mut self, hosts: Vec<String>) {
+        self.last_seed_resolve = time::now_utc();
+
+        let request_tx = self.request_tx.clone();
+
+        thread::spawn(move || {
+            let mut resolved = Vec::new();
+
+            for host in &hosts {
+                match host.to_socket_addrs() {
+                    Ok(addrs) => resolved.extend(addrs),
+                    Err(e) => println!("WARNING: Could not resolve seed host {}: {}", host, e),
+                }
+            }
+
+            let _ = request_tx.send(InternalRequest::SeedHostsResolved(resolved));
+        });
+    }
+
+    fn add_resolved_seeds(&mut self, addrs: Vec<SocketAddr>) {
+        for addr in addrs {
+            if !self.members.has_member(&addr) {
+                self.add_seed(addr, 0);
+            }
+        }
+    }
+
+    fn enqueue_seed_nodes(&mut self) {
+        let seeds = seeds_to_ping(&self.seed_queue, self.seed_rounds, self.config.seed_tier_rounds);
+        self.seed_rounds += 1;
+
+        for seed_node in seeds {
+            self.send_internal(InternalRequest::React(TargetedRequest {
+                request: Request::Ping,
+                target: seed_node,
+            }));
+        }
+    }
+
+    fn enqueue_random_ping(&mut self) {
+        if let Some(member) = self.members.next_random_member() {
+            self.send_internal(InternalRequest::React(TargetedRequest {
+                request: Request::Ping,
+                target: member.remote_host().unwrap(),
+            }));
+        }
+    }
+
+    fn prune_timed_out_responses(&mut self) {
+        let now = time::now_utc();
+
+        let (mut remaining, expired): (Vec<_>, Vec<_>) = self.pending_responses
+            .iter()
+            .cloned()
+            .partition(| &(t, _, _, _) | t < now);
+
+        let unanswered: HashSet<SocketAddr> = expired.iter().map(|&(_, addr, _, _)| addr).collect();
+        for addr in unanswered {
+            for forward in self.forward_unanswered_ping_requests(addr) {
+                self.send_internal(InternalRequest::React(forward));
+            }
+        }
+
+        let mut expired_hosts = expired_hosts_by_timeout(&expired);
+
+        if self.config.ack_window_size > 0 {
+            for addr in &expired_hosts {
+                record_ping_outcome(&mut self.ping_outcomes, *addr, false, self.config.ack_window_size);
+            }
+
+            let outcomes = &self.ping_outcomes;
+            let members = &self.members;
+            let ratio = self.config.suspicion_failure_ratio;
+
+            expired_hosts.retain(|addr| {
+                members.member_for(addr).map_or(true, |m| m.state() != MemberState::Alive)
+                    || outcomes.get(addr).map_or(true, |o| failure_ratio(o) > ratio)
+            });
+        }
+
+        for addr in &expired_hosts {
+            if let Some(host_key) = self.members.host_key_for(addr) {
+                let local_addr = self.config.listen_addr;
+                self.add_suspicion_source(host_key, local_addr);
+            }
+        }
+
+        let suspicion_timeout = self.suspicion_timeout();
+        let max_suspicions = self.escalation_budget();
+        let (suspect, down, deferred) = {
+            let sources = &self.suspicion_sources;
+            let required = self.config.suspicion_confirmations;
+            let observers = self.members.count_in_state(MemberState::Alive);
+
+            self.members.time_out_nodes(expired_hosts,
+                                        suspicion_timeout,
+                                        self.config.new_member_grace,
+                                        max_suspicions,
+                                        &self.escalated_pings,
+                                        |m| suspicion_confirmed(sources.get(&m.host_key()).map_or(0, |s| s.len()),
+                                                                required,
+                                                                observers))
+        };
+
+        remaining.extend(expired.into_iter().filter(|&(_, a, _, _)| deferred.contains(&a)));
+        self.pending_responses = remaining;
+
+        let (escalated, suspect): (Vec<_>, Vec<_>) = suspect
+            .into_iter()
+            .partition(|m| self.escalated_pings.contains(&m.remote_host().unwrap()));
+        self.escalations_this_period += suspect.len();
+
+        {
+            let pending = &self.pending_responses;
+            self.escalated_pings.retain(|addr| pending.iter().any(|&(_, target, _, _)| target == *addr));
+        }
+
+        enqueue_state_change(&mut self.state_changes, &down);
+        enqueue_state_change(&mut self.state_changes, &escalated);
+        enqueue_state_change(&mut self.state_changes, &suspect);
+
+        for member in &suspect {
+            self.send_ping_requests(member);
+        }
+
+        // Escalated hosts already have indirect pings in flight, so they are only marked as suspected
+        for member in escalated.into_iter().chain(suspect) {
+            self.timeline.record(TimelineEventKind::SuspicionStarted, member.remote_host().unwrap());
+            self.send_member_event(MemberEvent::MemberSuspectedDown(member.clone()));
+
+            if self.ping_observations.contains_key(&(member.host_key(), self.host_key)) {
+                println!("WARNING: {:?} is receiving our pings but not answering them, the partition looks asymmetric",
+                         member);
+                self.send_member_event(MemberEvent::AsymmetricPartition(member.clone()));
+            }
+        }
+
+        for member in down {
+            self.timeline.record(TimelineEventKind::MemberDown, member.remote_host().unwrap());
+            self.suspicion_sources.remove(&member.host_key());
+            self.ping_outcomes.remove(&member.remote_host().unwrap());
+            self.rtt_estimates.remove(&member.remote_host().unwrap());
+
+            let (host_key, addr) = (member.host_key(), member.remote_host().unwrap());
+            self.state_change_sequences.retain(|&(from, key), _| from != addr && key != host_key);
+            self.send_member_event(MemberEvent::MemberWentDown(member.clone()));
+        }
+
+        self.update_joined();
+    }
+
+    fn update_joined(&mut self) {
+        self.joined = self.members.has_alive_remote();
+
+        if self.joined && !self.ever_joined {
+            self.ever_joined = true;
+
+            if self.config.emit_joined_event {
+                let myself = self.members.myself();
+                self.send_member_event(MemberEvent::Joined(myself));
+            }
+        }
+    }
+
+    fn add_suspicion_source(&mut self, host_key: Uuid, source: SocketAddr) {
+        match self.suspicion_sources.entry(host_key) {
+            Entry::Occupied(mut entry) => { entry.get_mut().insert(source); },
+            Entry::Vacant(entry) => { entry.insert(vec![source].into_iter().collect()); },
+        };
+    }
+
+    fn escalate_unacked_ping(&mut self, target: SocketAddr) {
+        if !self.pending_responses.iter().any(|&(_, addr, _, _)| addr == target) {
+            return;
+        }
+
+        // Over budget, the regular timeout picks the target up once the cap allows
+        if self.escalation_budget() == 0 {
+            return;
+        }
+
+        if let Some(member) = self.members.member_for(&target) {
+            if member.state() == MemberState::Alive && self.escalated_pings.insert(target) {
+                self.escalations_this_period += 1;
+                self.send_ping_requests(&member);
+            }
+        }
+    }
+
+    /// Responses are pruned on every request, so the suspicion cap is tracked
+    /// across the whole protocol period, shared with fast-path escalations
+    /// that also fan out indirect pings.
+    fn escalation_budget(&self) -> usize {
+        self.config.max_suspicions_per_period
+            .map_or(std::usize::MAX, |max| max.saturating_sub(self.escalations_this_period))
+    }
+
+    fn suspicion_timeout(&self) -> Duration {
+        if self.config.adaptive_suspicion {
+            memberlist::scaled_suspicion_timeout(self.config.suspicion_timeout, self.members.len())
+        }
+        else {
+            self.config.suspicion_timeout
+        }
+    }
+
+    fn send_mtu_probes(&self, target: SocketAddr) {
+        let mut size = min_network_mtu(&self.config);
+
+        loop {
+            self.send_internal(InternalRequest::React(TargetedRequest {
+                request: Request::MtuProbe(size, String::new()),
+                target: target,
+            }));
+
+            if size >= self.config.network_mtu {
+                break;
+            }
+
+            size = std::cmp::min(size * 2, self.config.network_mtu);
+        }
+    }
+
+    fn send_ping_requests(&mut self, target: &Member) {
+        if self.monitored.is_some() {
+            return;
+        }
+
+        if let Some(target_host) = target.remote_host() {
+            self.timeline.record(TimelineEventKind::PingRequestSent, target_host);
+
+            let relays = {
+                let rtts = if self.config.prefer_low_rtt_relays { Some(&self.rtt_estimates) } else { None };
+                self.members.hosts_for_indirect_ping(self.config.ping_request_host_count,
+                                                     &target_host,
+                                                     rtts,
+                                                     self.config.relay_ack_window)
+            };
+
+            for relay in relays {
+                self.send_internal(InternalRequest::React(TargetedRequest {
+                    request: indirect_ping_request(&target_host, self.config.max_indirect_hops),
+                    target: relay,
+                }));
+            }
+        }
+    }
+
+    fn forward_ping_request(&mut self, requester: SocketAddr, target: SocketAddr, hops: u8) -> Option<TargetedRequest> {
+        if hops == 0 {
+            return None;
+        }
+
+        self.members
+            .hosts_for_indirect_ping(2, &target, None, self.config.relay_ack_window)
+            .into_iter()
+            .find(|&relay| relay != requester)
+            .map(|relay| TargetedRequest {
+                request: indirect_ping_request(&target, hops - 1),
+                target: relay,
+            })
+    }
+
+    /// Waits on our own ping to `target` before passing a ping request on,
+    /// so the next relay is only involved if we could not reach the target.
+    fn accept_ping_request(&mut self, requester: SocketAddr, target: SocketAddr, hops: u8) -> Option<TargetedRequest> {
+        let expiry = time::now_utc() + self.config.wait_list_timeout;
+
+        if !add_to_wait_list(&mut self.wait_list, &target, &requester, expiry, self.config.max_wait_list_size) {
+            println!("WARNING: Wait list is full, ignoring ping request for {}", target);
+            return None;
+        }
+
+        if hops > 0 {
+            self.pending_forwards.push((target, requester, hops));
+        }
+
+        Some(TargetedRequest { request: Request::Ping, target: target })
+    }
+
+    fn forward_unanswered_ping_requests(&mut self, target: SocketAddr) -> Vec<TargetedRequest> {
+        let (forwards, remaining): (Vec<_>, Vec<_>) = self.pending_forwards
+            .iter()
+            .cloned()
+            .partition(|&(addr, _, _)| addr == target);
+        self.pending_forwards = remaining;
+
+        forwards.into_iter()
+            .filter_map(|(addr, requester, hops)| self.forward_ping_request(requester, addr, hops))
+            .collect()
+    }
+
+    fn process_internal_request(&mut self, message: InternalRequest) -> Option<Sender<()>> {
+        use InternalRequest::*;
+
+        match message {
+            AddSeed(addr, priority) => self.add_seed(addr, priority),
+            Join(seeds, deadline, tx) => {
+                if seeds.is_empty() {
+                    let _ = tx.send(Err(JoinError::AllSeedsRejected));
+                }
+                else {
+                    for &addr in &seeds {
+                        self.add_seed(addr, 0);
+                    }
+
+                    self.join_waiters.push((seeds, deadline, tx));
+                    self.enqueue_seed_nodes();
+                }
+            },
+            AddSeedHost(host) => {
+                self.seed_hosts.push(host.clone());
+                self.resolve_seed_hosts(vec![host]);
+            },
+            SeedHostsResolved(addrs) => self.add_resolved_seeds(addrs),
+            ReplaceSeeds(seeds) => {
+                self.seed_queue.clear();
+
+                for addr in seeds {
+                    if !self.members.has_member(&addr) {
+                        self.add_seed(addr, 0);
+                    }
+                }
+            },
+            Respond(src_addr, message) => self.respond_to_message(src_addr, message),
+            React(request) => {
+                self.prune_timed_out_responses();
+                self.process_request(request);
+            },
+            LeaveCluster => {
+                if allow_self_transition(&self.config, self.members.myself().state(), MemberState::Left) {
+                    let myself = self.members.leave();
+                    enqueue_state_change(&mut self.state_changes, &[myself]);
+                }
+                else {
+                    println!("WARNING: Leaving the cluster was vetoed by the self state hook");
+                }
+            },
+            Flush => {
+                for _ in 0..self.config.ping_request_host_count {
+                    self.enqueue_random_ping();
+                }
+            },
+            SetTags(tags) => {
+                let myself = self.members.set_tags(tags);
+                enqueue_state_change(&mut self.state_changes, &[myself]);
+            },
+            MembersWithTag(tag, tx) => tx.send(self.members.members_with_tag(&tag)).unwrap(),
+            RecentTransitions(host_key, tx) => tx.send(self.members.recent_transitions(&host_key)).unwrap(),
+            MembersPage(offset, limit, tx) => tx.send(self.members.available_nodes_page(offset, limit)).unwrap(),
+            SetConfigEpoch(config_epoch) => {
+                let myself = self.members.set_config_epoch(config_epoch);
+                enqueue_state_change(&mut self.state_changes, &[myself]);
+            },
+            MinConfigEpoch(tx) => tx.send(self.members.min_config_epoch()).unwrap(),
+            EstimatedSize(tx) => tx.send(self.members.estimated_size()).unwrap(),
+            PendingChangeCount(tx) => tx.send(self.state_changes.len()).unwrap(),
+            Incarnation(tx) => tx.send(self.members.myself().incarnation()).unwrap(),
+            EffectiveConfig(tx) => tx.send(redacted_config(&self.config)).unwrap(),
+            RotateClusterKey => self.rotate_cluster_key(),
+            LoadMembers(members) => self.load_members(members),
+            JoinedVia(tx) => tx.send(self.joined_via).unwrap(),
+            ClearSuspicion(host_key) => self.clear_suspicion(host_key),
+            MemberByAddr(addr, tx) => tx.send(self.members.member_for(&addr)).unwrap(),
+            ForEachMember(mut f, tx) => {
+                self.members.for_each_available(|m| f(m));
+                let _ = tx.send(());
+            },
+            MuteMember(host_key, until) => { self.muted_members.insert(host_key, until); },
+            SyncWith(addr) => self.process_request(TargetedRequest { request: Request::Sync, target: addr }),
+            ViewSnapshot(tx) => tx.send(view::ViewSnapshot::new(&self.members.available_nodes())).unwrap(),
+            PingMember(host_key, deadline, tx) => {
+                match self.members.member_by_host_key(&host_key).and_then(|m| m.remote_host()) {
+                    Some(addr) => {
+                        self.ping_waiters.push((host_key, deadline, tx));
+                        self.send_internal(InternalRequest::React(TargetedRequest {
+                            request: Request::Ping,
+                            target: addr,
+                        }));
+                    },
+                    None => { let _ = tx.send(false); },
+                }
+            },
+            WaitForDeparture(host_key, deadline, tx) => {
+                match self.members.member_by_host_key(&host_key) {
+                    Some(ref m) if m.state() != MemberState::Down && m.state() != MemberState::Left => {
+                        self.departure_waiters.push((host_key, deadline, tx));
+                    },
+                    _ => { let _ = tx.send(true); },
+                }
+            },
+            IsJoined(tx) => tx.send(self.joined).unwrap(),
+            Timeline(tx) => tx.send(self.timeline.entries()).unwrap(),
+            Subscribe(mask, tx) => self.subscribers.push((mask, tx)),
+            SubscribeSequenced(tx) => self.sequenced_subscribers.push(tx),
+            ForwardEvents(addr) => self.forwarders.push(EventForwarder::spawn(addr)),
+            #[cfg(feature = "tokio")]
+            SubscribeAsync(tx) => self.async_subscribers.push(tx),
+            #[cfg(feature = "chaos")]
+            SetChaos(drop_rate) => self.chaos_drop_rate = drop_rate,
+            Stats(tx) => tx.send(self.stats()).unwrap(),
+            DropCounts(tx) => tx.send(self.counters.drop_counts.clone()).unwrap(),
+            Exit(tx) => return Some(tx),
+        };
+
+        None
+    }
+
+    fn accepts_cluster_key(&self, cluster_key: &Vec<u8>) -> bool {
+        if *cluster_key == self.cluster_key || self.config.accepted_cluster_keys.contains(cluster_key) {
+            return true;
+        }
+
+        match self.previous_cluster_key {
+            Some((ref previous, expires)) => previous == cluster_key && time::now_utc() < expires,
+            None => false,
+        }
+    }
+
+    fn accepted_cluster_keys(&self) -> Vec<&Vec<u8>> {
+        let mut keys = vec![&self.cluster_key];
+        keys.extend(self.config.accepted_cluster_keys.iter());
+
+        if let Some((ref previous, expires)) = self.previous_cluster_key {
+            if time::now_utc() < expires {
+                keys.push(previous);
+            }
+        }
+
+        keys
+    }
+
+    fn rotate_cluster_key(&mut self) {
+        let cluster_key = resolve_cluster_key(&self.config);
+
+        if cluster_key == self.cluster_key {
+            return;
+        }
+
+        println!("INFO: Rotated the cluster key, accepting the previous one for {}s",
+                 self.config.cluster_key_grace_period.num_seconds());
+
+        let previous = std::mem::replace(&mut self.cluster_key, cluster_key);
+        self.previous_cluster_key = Some((previous, time::now_utc() + self.config.cluster_key_grace_period));
+    }
+
+    fn expire_previous_cluster_key(&mut self) {
+        let expired = match self.previous_cluster_key {
+            Some((_, expires)) => expires <= time::now_utc(),
+            None => false,
+        };
+
+        if expired {
+            self.previous_cluster_key = None;
+        }
+    }
+
+    fn stats(&self) -> ClusterStats {
+        ClusterStats {
+            alive_members: self.members.count_in_state(MemberState::Alive),
+            suspect_members: self.members.count_in_state(MemberState::Suspect),
+            down_members: self.members.count_in_state(MemberState::Down),
+            left_members: self.members.count_in_state(MemberState::Left),
+            outstanding_state_changes: self.state_changes.len(),
+            pending_responses: self.pending_responses.len(),
+            ping_interval: self.config.ping_interval,
+            packets_sent: self.counters.packets_sent,
+            packets_received: self.counters.packets_received,
+            bytes_sent: self.counters.bytes_sent,
+            bytes_received: self.counters.bytes_received,
+            packets_dropped: self.counters.packets_dropped,
+            chaos_dropped: self.counters.chaos_dropped,
+            checksum_failures: self.counters.checksum_failures,
+            identity_rejections: self.counters.identity_rejections,
+            internal_requests_dropped: self.counters.internal_requests_dropped,
+            protocol_version: PROTOCOL_VERSION,
+            drop_counts: self.counters.drop_counts.clone(),
+            rate_limited_sends: self.counters.rate_limited_sends,
+        }
+    }
+
+    fn respond_to_message(&mut self, src_addr: SocketAddr, message: Message) {
+        use Request::*;
+
+        if !self.accepts_cluster_key(&message.cluster_key) {
+            println!("ERROR: Mismatching cluster keys, ignoring {} from {}", message.summary(), src_addr);
+            self.counters.record_dropped(DropReason::ClusterKeyMismatch);
+
+            if message.request == Reject {
+                self.seed_rejected(src_addr);
+            }
+            else if self.should_reject(src_addr) {
+                self.send_internal(InternalRequest::React(TargetedRequest {
+                    request: Reject,
+                    target: src_addr,
+                }));
+            }
+        }
+        else {
+            self.check_protocol_version(src_addr, &message);
+
+            if let Some(window) = self.config.asymmetry_window {
+                let now = time::now_utc();
+
+                for &(observer, pinger) in message.ping_observations.iter().flat_map(|o| o) {
+                    record_ping_observation(&mut self.ping_observations, observer, pinger, now, window);
+                }
+
+                if message.request == Ping {
+                    record_ping_observation(&mut self.ping_observations, self.host_key, message.sender, now, window);
+                }
+            }
+
+            // Older peers send no sequence numbers, so their state changes are applied as they arrive
+            let state_changes = match message.sequence {
+                Some(sequence) if self.config.ordered_state_changes => {
+                    discard_reordered_state_changes(&mut self.state_change_sequences,
+                                                    src_addr,
+                                                    sequence,
+                                                    message.state_changes)
+                },
+                _ => message.state_changes,
+            };
+
+            if self.monitored.is_none() {
+                self.apply_state_changes(state_changes, src_addr);
+            }
+
+            if remove_potential_seed(&mut self.seed_queue, src_addr) && self.joined_via.is_none() {
+                println!("INFO: Joined the cluster via seed {}", src_addr);
+                self.joined_via = Some(src_addr);
+            }
+
+            // Monitors only observe, so they never become members of the clusters they watch
+            if message.monitor != Some(true) {
+                self.ensure_node_is_member(src_addr, message.sender);
+            }
+
+            let remote_time = message.timestamp_ms;
+            let response = match message.request {
+                Ping => Some(TargetedRequest { request: Ack, target: src_addr }),
+                Ack => {
+                    self.timeline.record(TimelineEventKind::AckReceived, src_addr);
+                    if let Some(remote_time) = remote_time {
+                        self.record_clock_offset(src_addr, remote_time);
+                    }
+                    self.seed_acked(src_addr);
+                    self.notify_ping_waiters(message.sender);
+                    self.ack_response(src_addr);
+                    self.mark_node_alive(src_addr);
+                    None
+                },
+                PingRequest(dest_addr) => {
+                    let EncSocketAddr(dest_addr) = dest_addr;
+                    self.accept_ping_request(src_addr, dest_addr, 0)
+                },
+                PingRequestWithHops(dest_addr, hops) => {
+                    let EncSocketAddr(dest_addr) = dest_addr;
+                    self.accept_ping_request(src_addr, dest_addr, hops)
+                },
+                AckHost(member) => {
+                    self.ack_response(member.remote_host().unwrap());
+                    self.mark_node_alive(member.remote_host().unwrap());
+                    None
+                },
+                PingRequestFailed(dest_addr) => {
+                    let EncSocketAddr(dest_addr) = dest_addr;
+
+                    if let Some(host_key) = self.members.host_key_for(&dest_addr) {
+                        self.add_suspicion_source(host_key, src_addr);
+                    }
+                    None
+                },
+                MtuProbe(size, _) => Some(TargetedRequest { request: MtuProbeAck(size), target: src_addr }),
+                MtuProbeAck(size) => {
+                    if size <= self.config.network_mtu && size > self.effective_mtu {
+                        self.effective_mtu = size;
+                    }
+                    None
+                },
+                Reject => None,
+                Sync => Some(TargetedRequest { request: SyncAck, target: src_addr }),
+                SyncAck => None,
+            };
+
+            match response {
+                Some(response) => self.send_internal(InternalRequest::React(response)),
+                None => (),
+            };
+
+            self.update_joined();
+        }
+    }
+
+    /// Peers on other versions are still served, since newer fields are
+    /// optional, but each one is reported once so mixed rollouts are visible.
+    fn check_protocol_version(&mut self, src_addr: SocketAddr, message: &Message) {
+        let version = message.protocol_version.unwrap_or(UNVERSIONED_PROTOCOL);
+
+        if version != PROTOCOL_VERSION && self.version_mismatches.insert(src_addr) {
+            println!("WARNING: {} speaks protocol version {}, this node speaks {}",
+                     src_addr, version, PROTOCOL_VERSION);
+        }
+    }
+
+    fn record_clock_offset(&mut self, src_addr: SocketAddr, remote_time_ms: i64) {
+        let sent_at = self.pending_responses
+            .iter()
+            .filter(|&&(_, addr, _, _)| addr == src_addr)
+            .map(|&(_, _, _, sent_at)| sent_at)
+            .max();
+        let rtt = sent_at.and_then(|sent_at| Duration::from_std(sent_at.elapsed()).ok());
+
+        if let Some(rtt) = rtt {
+            let midpoint = time::now_utc() - rtt / 2;
+            let offset = Duration::milliseconds(remote_time_ms) - (midpoint.to_timespec() - time::Timespec::new(0, 0));
+
+            if offset.num_milliseconds().abs() > self.config.clock_skew_warning.num_milliseconds() {
+                println!("WARNING: Clock of {} appears to be off by {} ms", src_addr, offset.num_milliseconds());
+            }
+
+            self.members.set_clock_offset(&src_addr, offset);
+        }
+    }
+
+    fn ack_response(&mut self, src_addr: SocketAddr) {
+        let mut to_remove = Vec::new();
+
+        self.members.record_ack(&src_addr);
+        self.escalated_pings.remove(&src_addr);
+
+        for &(ref t, ref addr, ref state_changes, sent_at) in self.pending_responses.iter() {
+            if src_addr != *addr {
+                continue;
+            }
+
+            to_remove.push((t.clone(), addr.clone(), state_changes.clone(), sent_at));
+            record_ping_outcome(&mut self.ping_outcomes, src_addr, true, self.config.ack_window_size);
+
+            if let Ok(rtt) = Duration::from_std(sent_at.elapsed()) {
+                update_rtt_estimate(&mut self.rtt_estimates, src_addr, rtt);
+            }
+
+            self.state_changes
+                .retain(|os| !state_changes.iter().any(| is | is.member().host_key() == os.member().host_key()))
+        }
+
+        self.pending_responses.retain(|op| !to_remove.iter().any(|ip| ip == op));
+    }
+
+    fn ensure_node_is_member(&mut self, src_addr: SocketAddr, sender: Uuid) {
+        if self.sender_cache.contains(&src_addr, &sender) {
+            return;
+        }
+
+        if self.members.has_member(&src_addr) || self.members.has_host_key(&sender) {
+            self.sender_cache.insert(src_addr, sender);
+            return;
+        }
+
+        if self.config.identity.is_some() {
+            println!("WARNING: Ignoring unverified sender {} from {}", sender.to_hyphenated_string(), src_addr);
+            self.counters.record_identity_rejection();
+            return;
+        }
+
+        if let Some(ref monitored) = self.monitored {
+            if !monitored.contains(&src_addr) {
+                return;
+            }
+        }
+
+        let initial_state = if self.config.assume_new_members_alive || self.monitored.is_some() {
+            MemberState::Alive
+        } else {
+            MemberState::Suspect
+        };
+        let new_member = Member::new(sender, src_addr, 0, initial_state);
+
+        self.members.add_member(new_member.clone());
+
+        if initial_state == MemberState::Alive {
+            enqueue_state_change(&mut self.state_changes, &[new_member.clone()]);
+        }
+
+        self.announce_myself();
+        self.send_member_event(MemberEvent::MemberJoined(new_member));
+    }
+
+    fn send_member_event(&mut self, event: MemberEvent) {
+        use MemberEvent::*;
+
+        match event {
+            MemberJoined(_) => {},
+            MemberWentUp(ref m) => assert_eq!(m.state(), MemberState::Alive),
+            MemberWentDown(ref m) => assert_eq!(m.state(), MemberState::Down),
+            MemberSuspectedDown(ref m) => assert_eq!(m.state(), MemberState::Suspect),
+            MemberLeft(ref m) => assert_eq!(m.state(), MemberState::Left),
+            SuspicionCleared(ref m) => assert_eq!(m.state(), MemberState::Alive),
+            Joined(ref m) => assert!(m.is_myself()),
+            SocketFailed(ref m) => assert!(m.is_myself()),
+            EventsSummarized(ref m, _) => assert!(m.is_myself()),
+            Heartbeat(ref m, _) => assert!(m.is_myself()),
+            AsymmetricPartition(ref m) => assert!(!m.is_myself()),
+        };
+
+        match event {
+            MemberWentDown(ref m) | MemberLeft(ref m) => self.notify_departure_waiters(m.host_key()),
+            _ => {},
+        };
+
+        self.coalesced_changes.remove(&event.member().host_key());
+
+        if self.monitored.is_some() && !is_monitor_event(&event) {
+            return;
+        }
+
+        if self.is_muted(&event.member().host_key()) {
+            return;
+        }
+
+        if self.config.log_full_membership {
+            self.log_membership(&event);
+        }
+
+        self.queued_events.push(event);
+    }
+
+    fn is_muted(&mut self, host_key: &Uuid) -> bool {
+        let now = time::now_utc();
+        self.muted_members.retain(|_, &mut until| until > now);
+
+        self.muted_members.contains_key(host_key)
+    }
+
+    fn log_membership(&self, event: &MemberEvent) {
+        let (members, _) = self.members.available_nodes_page(0, std::usize::MAX);
+
+        println!("INFO: Membership after {:?}: {}", event, format_membership(&members));
+    }
+
+    fn dispatch_member_events(&mut self) {
+        let mut events: Vec<_> = self.queued_events.drain(..).collect();
+        sort_member_events(&mut events, &*self.config.event_priority);
+
+        for event in events {
+            if self.config.event_flush_interval.is_some() {
+                coalesce_member_event(&mut self.pending_events, event);
+            }
+            else {
+                let members = self.members.available_nodes();
+                self.deliver_member_event(members, event);
+            }
+        }
+    }
+
+    fn notify_departure_waiters(&mut self, host_key: Uuid) {
+        self.departure_waiters.retain(|&(waiting_for, _, ref tx)| {
+            if waiting_for == host_key {
+                let _ = tx.send(true);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn prune_departure_waiters(&mut self) {
+        let now = time::now_utc();
+
+        self.departure_waiters.retain(|&(_, deadline, ref tx)| {
+            if deadline < now {
+                let _ = tx.send(false);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn deliver_member_event(&mut self, members: Vec<Member>, event: MemberEvent) {
+        if let Some(max_events) = self.config.max_events_per_period {
+            if self.period_event_count >= max_events {
+                summarize_member_event(&mut self.summarized_members, event.member().clone());
+                return;
+            }
+
+            self.period_event_count += 1;
+        }
+
+        self.emit_member_event(members, event);
+    }
+
+    fn flush_event_summary(&mut self) {
+        self.period_event_count = 0;
+
+        if self.summarized_members.is_empty() {
+            return;
+        }
+
+        let summarized = self.summarized_members.drain(..).collect();
+        let event = MemberEvent::EventsSummarized(self.members.myself(), summarized);
+        let members = self.members.available_nodes();
+
+        self.emit_member_event(members, event);
+    }
+
+    fn emit_heartbeat(&mut self) {
+        let members = self.members.available_nodes();
+        let event = MemberEvent::Heartbeat(self.members.myself(), ViewSnapshot::new(&members));
+
+        self.emit_member_event(members, event);
+    }
+
+    fn emit_member_event(&mut self, members: Vec<Member>, event: MemberEvent) {
+        self.event_seq += 1;
+
+        if !self.sequenced_subscribers.is_empty() {
+            let sequenced = SequencedEvent { seq: self.event_seq, members: members.clone(), event: event.clone() };
+
+            self.sequenced_subscribers.retain(|tx| tx.send(sequenced.clone()).is_ok());
+        }
+
+        self.subscribers.retain(|&(mask, ref tx)| {
+            !mask.matches(&event) || tx.send((members.clone(), event.clone())).is_ok()
+        });
+
+        if !self.forwarders.is_empty() {
+            let cluster_event = (members.clone(), event.clone());
+
+            self.forwarders.retain(|forwarder| forwarder.forward(&cluster_event));
+        }
+
+        self.deliver_async_member_event(&members, &event);
+
+        self.event_sink.on_event(&(members, event));
+    }
+
+    #[cfg(feature = "tokio")]
+    fn deliver_async_member_event(&mut self, members: &[Member], event: &MemberEvent) {
+        use tokio::sync::mpsc::error::TrySendError;
+
+        self.async_subscribers.retain(|tx| {
+            match tx.try_send((members.to_vec(), event.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    println!("WARNING: Async event channel is full, dropping {:?}", event);
+                    true
+                },
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn deliver_async_member_event(&mut self, _members: &[Member], _event: &MemberEvent) {
+    }
+
+    fn flush_member_events(&mut self) {
+        self.last_event_flush = time::now_utc();
+
+        if self.pending_events.is_empty() {
+            return;
+        }
+
+        let members = self.members.available_nodes();
+
+        let events: Vec<_> = self.pending_events.drain(..).collect();
+
+        for event in events {
+            self.deliver_member_event(members.clone(), event);
+        }
+    }
+
+    fn apply_state_changes(&mut self, state_changes: Vec<StateChange>, from: SocketAddr) {
+        let max_metadata_bytes = self.config.max_metadata_bytes;
+        let mut verified = Vec::with_capacity(state_changes.len());
+
+        for state_change in state_changes {
+            if self.verify_identity(state_change.member()) {
+                verified.push(enforce_metadata_limit(state_change, max_metadata_bytes));
+            }
+            else {
+                println!("WARNING: Could not verify the identity of {:?} from {}, ignoring it",
+                         state_change.member(), from);
+                self.counters.record_identity_rejection();
+            }
+        }
+
+        let state_changes: Vec<_> = verified
+            .into_iter()
+            .filter(|sc| !suspicion_was_cleared(&self.cleared_suspicions, sc.member()))
+            .collect();
+
+        for state_change in &state_changes {
+            let member = state_change.member();
+
+            if member.state() == MemberState::Suspect && member.host_key() != self.host_key {
+                self.add_suspicion_source(member.host_key(), from);
+            }
+        }
+
+        let reported_alive: HashSet<Uuid> = state_changes
+            .iter()
+            .filter(|sc| sc.member().state() == MemberState::Alive)
+            .map(|sc| sc.member().host_key())
+            .collect();
+
+        let (new, changed, updated) = {
+            let config = &self.config;
+
+            self.members.apply_state_changes(state_changes,
+                                             &from,
+                                             &*config.conflict_resolver,
+                                             config.assume_new_members_alive,
+                                             |reported| allow_self_transition(config, reported, MemberState::Alive))
+        };
+
+        let changed_members: Vec<_> = changed.iter().map(|&(_, ref m)| m.clone()).collect();
+
+        for member in changed_members.iter().chain(updated.iter()) {
+            self.sender_cache.invalidate(&member.host_key());
+        }
+
+        {
+            let members = &self.members;
+
+            self.cleared_suspicions.retain(|host_key, &mut incarnation| {
+                members.member_by_host_key(host_key).map_or(false, |m| m.incarnation() <= incarnation)
+            });
+        }
+
+        // New members held as Suspect until we reach them ourselves were reported alive, so that stays local
+        let gossiped_new: Vec<_> = new
+            .iter()
+            .filter(|m| m.state() != MemberState::Suspect || !reported_alive.contains(&m.host_key()))
+            .cloned()
+            .collect();
+
+        enqueue_state_change(&mut self.state_changes, &gossiped_new);
+        enqueue_state_change(&mut self.state_changes, &changed_members);
+
+        if !new.is_empty() {
+            self.announce_myself();
+        }
+
+        enqueue_state_change(&mut self.state_changes, &updated);
+
+        for member in new {
+            self.send_member_event(MemberEvent::MemberJoined(member));
+        }
+
+        for (previous_state, member) in changed {
+            if member.state() != MemberState::Suspect {
+                self.suspicion_sources.remove(&member.host_key());
+            }
+
+            if self.config.coalesce_state_changes && member.host_key() != self.host_key {
+                self.coalesced_changes.entry(member.host_key()).or_insert(previous_state);
+            }
+            else {
+                self.send_member_event(determine_member_event(previous_state, member));
+            }
+        }
+    }
+
+    fn flush_coalesced_changes(&mut self) {
+        let coalesced: Vec<_> = self.coalesced_changes.drain().collect();
+
+        for (host_key, previous_state) in coalesced {
+            if let Some(member) = self.members.member_by_host_key(&host_key) {
+                if member.state() != previous_state {
+                    self.send_member_event(determine_member_event(previous_state, member));
+                }
+            }
+        }
+    }
+
+    fn load_members(&mut self, members: Vec<Member>) {
+        let (added, changed, updated, removed) = self.members.load_members(members);
+        let changed_members: Vec<_> = changed.iter().map(|&(_, ref m)| m.clone()).collect();
+
+        enqueue_state_change(&mut self.state_changes, &added);
+        enqueue_state_change(&mut self.state_changes, &changed_members);
+        enqueue_state_change(&mut self.state_changes, &updated);
+
+        for member in added {
+            self.send_member_event(MemberEvent::MemberJoined(member));
+        }
+
+        for (previous_state, member) in changed {
+            self.sender_cache.invalidate(&member.host_key());
+            self.send_member_event(determine_member_event(previous_state, member));
+        }
+
+        for member in &updated {
+            self.sender_cache.invalidate(&member.host_key());
+        }
+
+        for mut member in removed {
+            let (host_key, addr) = (member.host_key(), member.remote_host().unwrap());
+
+            self.sender_cache.invalidate(&host_key);
+            self.suspicion_sources.remove(&host_key);
+            self.pending_responses.retain(|&(_, target, _, _)| target != addr);
+            self.ping_deadlines.retain(|&target| target != addr);
+            self.pending_forwards.retain(|&(target, _, _)| target != addr);
+            self.wait_list.remove(&addr);
+            self.rtt_estimates.remove(&addr);
+            self.ping_outcomes.remove(&addr);
+            self.state_change_sequences.retain(|&(from, key), _| from != addr && key != host_key);
+            self.state_changes.retain(|c| c.member().host_key() != host_key);
+
+            // Removals are local only, so the Left state below is never enqueued for gossip
+            member.set_state(MemberState::Left);
+            self.send_member_event(MemberEvent::MemberLeft(member));
+        }
+
+        self.update_joined();
+    }
+
+    fn mark_node_alive(&mut self, src_addr: SocketAddr) {
+        if let Some((previous_state, member)) = self.members.mark_node_alive(&src_addr) {
+            self.suspicion_sources.remove(&member.host_key());
+
+            if previous_state == MemberState::Suspect {
+                self.timeline.record(TimelineEventKind::SuspicionCleared, src_addr);
+            }
+
+            enqueue_state_change(&mut self.state_changes, &[member.clone()]);
+            self.send_member_event(determine_member_event(previous_state, member));
+        }
+
+        self.notify_wait_list(src_addr);
+    }
+
+    fn clear_suspicion(&mut self, host_key: Uuid) {
+        if let Some(member) = self.members.clear_suspicion(&host_key) {
+            let addr = member.remote_host().unwrap();
+
+            self.suspicion_sources.remove(&host_key);
+            self.pending_responses.retain(|&(_, target, _, _)| target != addr);
+            self.ping_deadlines.retain(|&target| target != addr);
+            self.pending_forwards.retain(|&(target, _, _)| target != addr);
+            self.wait_list.remove(&addr);
+            self.timeline.record(TimelineEventKind::SuspicionCleared, addr);
+
+            // Peers holding the suspicion at this incarnation would ignore an Alive from us, so ask
+            // the member to refute it instead
+            let mut claim = member.clone();
+            claim.set_state(MemberState::Suspect);
+            self.cleared_suspicions.insert(host_key, member.incarnation());
+            self.refutation_claims.insert(addr, StateChange::new(claim));
+
+            self.send_member_event(determine_member_event(MemberState::Suspect, member));
+            self.send_request(TargetedRequest { request: Request::Ping, target: addr }, false);
+        }
+    }
+
+    /// Rejects go back to an unauthenticated source address, so each address
+    /// gets at most one per protocol period and the total is capped.
+    fn should_reject(&mut self, src_addr: SocketAddr) -> bool {
+        if self.rejected_this_period.len() >= MAX_REJECTS_PER_PERIOD {
+            return false;
+        }
+
+        self.rejected_this_period.insert(src_addr)
+    }
+
+    fn seed_acked(&mut self, src_addr: SocketAddr) {
+        self.join_waiters.retain(|&(ref seeds, _, ref tx)| {
+            if seeds.contains(&src_addr) {
+                let _ = tx.send(Ok(()));
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn seed_rejected(&mut self, src_addr: SocketAddr) {
+        // Only a seed we are still waiting on can reject us, which keeps spoofed rejects from aborting a join
+        if !self.pending_responses.iter().any(|&(_, target, _, _)| target == src_addr) {
+            return;
+        }
+
+        for &mut (ref mut seeds, _, _) in self.join_waiters.iter_mut() {
+            seeds.retain(|&addr| addr != src_addr);
+        }
+
+        self.join_waiters.retain(|&(ref seeds, _, ref tx)| {
+            if seeds.is_empty() {
+                let _ = tx.send(Err(JoinError::AllSeedsRejected));
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn prune_join_waiters(&mut self) {
+        let now = time::now_utc();
+
+        self.join_waiters.retain(|&(_, deadline, ref tx)| {
+            if deadline < now {
+                let _ = tx.send(Err(JoinError::Timeout));
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    /// Matches on the acking node's host key rather than its address, so an
+    /// ack from a different node that took over the address does not count.
+    fn notify_ping_waiters(&mut self, sender: Uuid) {
+        self.ping_waiters.retain(|&(host_key, _, ref tx)| {
+            if host_key == sender {
+                let _ = tx.send(true);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn prune_ping_waiters(&mut self) {
+        let now = time::now_utc();
+
+        self.ping_waiters.retain(|&(_, deadline, ref tx)| {
+            if deadline < now {
+                let _ = tx.send(false);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    fn notify_wait_list(&mut self, src_addr: SocketAddr) {
+        for ack in self.acks_for_waiting_hosts(src_addr) {
+            self.send_internal(InternalRequest::React(ack));
+        }
+    }
+
+    fn acks_for_waiting_hosts(&mut self, src_addr: SocketAddr) -> Vec<TargetedRequest> {
+        self.pending_forwards.retain(|&(target, _, _)| target != src_addr);
+
+        match (self.wait_list.remove(&src_addr), self.members.member_for(&src_addr)) {
+            (Some(waiting), Some(member)) => waiting.into_iter()
+                .map(|(_, remote)| TargetedRequest { request: Request::AckHost(member.clone()), target: remote })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn prune_wait_list(&mut self) {
+        let expired = prune_wait_list(&mut self.wait_list, time::now_utc());
+
+        let wait_list = &self.wait_list;
+        self.pending_forwards.retain(|&(target, _, _)| wait_list.contains_key(&target));
+
+        if !self.config.notify_failed_ping_requests {
+            return;
+        }
+
+        for (target, remote) in expired {
+            self.send_internal(InternalRequest::React(TargetedRequest {
+                request: Request::PingRequestFailed(EncSocketAddr::from_addr(&target)),
+                target: remote,
+            }));
+        }
+    }
+}
+
+fn indirect_ping_request(target: &SocketAddr, hops: u8) -> Request {
+    // Only hop-carrying requests use the newer variant, so peers without forwarding support can still decode the rest
+    if hops > 0 {
+        Request::PingRequestWithHops(EncSocketAddr::from_addr(target), hops)
+    }
+    else {
+        Request::PingRequest(EncSocketAddr::from_addr(target))
+    }
+}
+
+fn enforce_metadata_limit(mut state_change: StateChange, max_metadata_bytes: usize) -> StateChange {
+    if state_change.member().metadata_size() > max_metadata_bytes {
+        println!("WARNING: Metadata for {:?} exceeds {} bytes, rejecting it",
+                 state_change.member(), max_metadata_bytes);
+
+        let mut member = state_change.member().clone();
+        member.set_tags(HashSet::new());
+        state_change.update(member);
+    }
+
+    state_change
+}
+
+fn suspicion_was_cleared(cleared: &HashMap<Uuid, u64>, member: &Member) -> bool {
+    member.state() == MemberState::Suspect
+        && cleared.get(&member.host_key()).map_or(false, |&incarnation| member.incarnation() <= incarnation)
+}
+
+fn allow_self_transition(config: &ClusterConfig, old: MemberState, new: MemberState) -> bool {
+    match config.self_state_hook {
+        Some(ref hook) => hook(old, new),
+        None => true,
+    }
+}
+
+fn drop_self_addressed_packet(local_addr: &SocketAddr,
+                              advertise_addr: Option<SocketAddr>,
+                              counters: &mut PacketCounters,
+                              src_addr: &SocketAddr) -> bool {
+    if *src_addr == *local_addr || Some(*src_addr) == advertise_addr {
+        println!("WARNING: Received packet from our own address {}, dropping it", src_addr);
+        counters.record_dropped(DropReason::SelfAddressed);
+        return true;
+    }
+
+    false
+}
+
+fn unbound_socket(addr: &SocketAddr) -> io::Result<UdpSocket> {
+    match *addr {
+        SocketAddr::V4(_) => UdpSocket::v4(),
+        SocketAddr::V6(_) => UdpSocket::v6(),
+    }
+}
+
+fn open_server_socket(config: &ClusterConfig, addr: &SocketAddr) -> io::Result<UdpSocket> {
+    let socket = try!(bind_server_socket(config, addr));
+
+    if let Some(dscp) = config.dscp {
+        try!(set_dscp(&socket, addr, dscp));
+    }
+
+    Ok(socket)
+}
+
+fn bind_server_socket(config: &ClusterConfig, addr: &SocketAddr) -> io::Result<UdpSocket> {
+    if !config.reuse_addr && !config.reuse_port {
+        return UdpSocket::bound(addr);
+    }
+
+    let builder = try!(match *addr {
+        SocketAddr::V4(_) => UdpBuilder::new_v4(),
+        SocketAddr::V6(_) => UdpBuilder::new_v6(),
+    });
+
+    try!(builder.reuse_address(config.reuse_addr));
+
+    if config.reuse_port {
+        try!(set_reuse_port(&builder));
+    }
+
+    let socket = try!(builder.bind(addr));
+    try!(socket.set_nonblocking(true));
+
+    Ok(from_std_socket(socket))
+}
+
+#[cfg(unix)]
+fn set_reuse_port(builder: &UdpBuilder) -> io::Result<()> {
+    use net2::unix::UnixUdpBuilderExt;
+
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_builder: &UdpBuilder) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "SO_REUSEPORT is not supported on this platform"))
+}
+
+/// Marks outgoing packets with the given DSCP value by setting `IP_TOS` (or
+/// `IPV6_TCLASS` for IPv6 sockets). Only supported on Unix platforms, and some
+/// operating systems or networks may ignore or rewrite the marking.
+#[cfg(unix)]
+fn set_dscp(socket: &UdpSocket, addr: &SocketAddr, dscp: u8) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    if dscp > 0b111111 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "DSCP value must fit in six bits"));
+    }
+
+    let (level, name) = match *addr {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_TOS),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+    };
+    let tos = (dscp << 2) as libc::c_int;
+
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(),
+                         level,
+                         name,
+                         &tos as *const libc::c_int as *const libc::c_void,
+                         std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(unix))]
+fn set_dscp(_socket: &UdpSocket, _addr: &SocketAddr, _dscp: u8) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "DSCP marking is not supported on this platform"))
+}
+
+fn open_discovery_socket(group: &SocketAddr) -> io::Result<UdpSocket> {
+    let (builder, bind_addr) = match *group {
+        SocketAddr::V4(group) => {
+            (try!(UdpBuilder::new_v4()),
+             SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), group.port())))
+        },
+        SocketAddr::V6(group) => {
+            (try!(UdpBuilder::new_v6()),
+             SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), group.port(), 0, 0)))
+        },
+    };
+
+    try!(builder.reuse_address(true));
+
+    let socket = try!(builder.bind(bind_addr));
+
+    match *group {
+        SocketAddr::V4(group) => try!(socket.join_multicast_v4(group.ip(), &Ipv4Addr::new(0, 0, 0, 0))),
+        SocketAddr::V6(group) => try!(socket.join_multicast_v6(group.ip(), 0)),
+    }
+
+    try!(socket.set_nonblocking(true));
+
+    Ok(from_std_socket(socket))
+}
+
+/// Announcements are authenticated with an HMAC keyed by the cluster key, so
+/// the key itself never goes out on the multicast group.
+fn announcement_mac(cluster_key: &[u8], sender: &Uuid, addr: &SocketAddr, timestamp_ms: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(cluster_key).expect("HMAC accepts keys of any length");
+
+    mac.update(sender.as_bytes());
+    mac.update(addr.to_string().as_bytes());
+
+    for shift in (0..8).rev() {
+        mac.update(&[(timestamp_ms >> (shift * 8)) as u8]);
+    }
+
+    mac
+}
+
+fn sign_announcement(cluster_key: &[u8], sender: Uuid, addr: SocketAddr, timestamp_ms: i64) -> Announcement {
+    let mac = announcement_mac(cluster_key, &sender, &addr, timestamp_ms);
+
+    Announcement {
+        sender: sender,
+        addr: EncSocketAddr(addr),
+        timestamp_ms: timestamp_ms,
+        mac: mac.finalize().into_bytes().to_vec(),
+    }
+}
+
+fn verify_announcement(announcement: &Announcement, cluster_keys: &[&Vec<u8>], now_ms: i64) -> bool {
+    if (now_ms - announcement.timestamp_ms).abs() > ANNOUNCEMENT_MAX_AGE_MS {
+        return false;
+    }
+
+    cluster_keys.iter().any(|key| {
+        announcement_mac(key, &announcement.sender, &announcement.addr.0, announcement.timestamp_ms)
+            .verify_slice(&announcement.mac)
+            .is_ok()
+    })
+}
+
+/// Nodes listening on a wildcard address announce it as-is, so the sender's
+/// IP is substituted to get an address the announced node can be reached on.
+fn announced_seed_addr(announced: SocketAddr, src_addr: SocketAddr) -> SocketAddr {
+    if is_unspecified(&announced.ip()) {
+        SocketAddr::new(src_addr.ip(), announced.port())
+    }
+    else {
+        announced
+    }
+}
+
+fn is_unspecified(ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V4(ip) => ip == Ipv4Addr::new(0, 0, 0, 0),
+        IpAddr::V6(ip) => ip == Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0),
+    }
+}
+
+#[cfg(unix)]
+fn from_std_socket(socket: std::net::UdpSocket) -> UdpSocket {
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    unsafe { UdpSocket::from_raw_fd(socket.into_raw_fd()) }
+}
+
+#[cfg(windows)]
+fn from_std_socket(socket: std::net::UdpSocket) -> UdpSocket {
+    use std::os::windows::io::{FromRawSocket, IntoRawSocket};
+
+    unsafe { UdpSocket::from_raw_socket(socket.into_raw_socket()) }
+}
+
+fn packet_overhead(config: &ClusterConfig) -> usize {
+    let checksum_overhead = if config.checksum { checksum::CHECKSUM_OVERHEAD } else { 0 };
+
+    checksum_overhead + config.wire_overhead
+}
+
+fn build_message(sender: &Uuid,
+                 cluster_key: &Vec<u8>,
+                 sequence: u64,
+                 request: Request,
+                 state_changes: Vec<StateChange>,
+                 network_mtu: usize) -> Option<Message> {
+    build_message_with_remainder(sender, cluster_key, sequence, request, state_changes, network_mtu)
+        .map(|(message, _)| message)
+}
+
+/// Like `build_message`, but also returns the state changes that did not fit.
+fn build_message_with_remainder(sender: &Uuid,
+                                cluster_key: &Vec<u8>,
+                                sequence: u64,
+                                request: Request,
+                                state_changes: Vec<StateChange>,
+                                network_mtu: usize) -> Option<(Message, Vec<StateChange>)> {
+    let mut message = Message {
+        protocol_version: Some(PROTOCOL_VERSION),
+        sender: sender.clone(),
+        cluster_key: cluster_key.clone(),
+        sequence: Some(sequence),
+        timestamp_ms: Some(wall_clock_ms()),
+        request: request,
+        state_changes: Vec::new(),
+        ping_observations: None,
+        monitor: None,
+    };
+
+    if json::encode(&message).unwrap().len() >= network_mtu {
+        return None;
+    }
+
+    let mut state_changes = state_changes.into_iter();
+
+    while let Some(state_change) = state_changes.next() {
+        message.state_changes.push(state_change);
+
+        let encoded = json::encode(&message).unwrap();
+        if encoded.len() >= network_mtu {
+            let state_change = message.state_changes.pop().unwrap();
+
+            if message.state_changes.is_empty() {
+                println!("WARNING: State change for {:?} does not fit in the network MTU, skipping it",
+                         state_change.member());
+                continue;
+            }
+
+            let remainder = Some(state_change).into_iter().chain(state_changes).collect();
+
+            return Some((message, remainder));
+        }
+    }
+
+    Some((message, Vec::new()))
+}
+
+fn record_ping_observation(observations: &mut HashMap<(Uuid, Uuid), time::Tm>,
+                           observer: Uuid,
+                           pinger: Uuid,
+                           now: time::Tm,
+                           window: Duration) {
+    if observer == pinger {
+        return;
+    }
+
+    observations.insert((observer, pinger), now);
+    observations.retain(|_, &mut seen| seen + window >= now);
+}
+
+fn recent_ping_observations(observations: &HashMap<(Uuid, Uuid), time::Tm>) -> Vec<(Uuid, Uuid)> {
+    let mut recent: Vec<_> = observations.iter().map(|(&key, &seen)| (seen, key)).collect();
+    recent.sort_by(|a, b| b.0.to_timespec().cmp(&a.0.to_timespec()));
+
+    recent.into_iter().map(|(_, key)| key).collect()
+}
+
+fn attach_ping_observations(message: &mut Message, mut observations: Vec<(Uuid, Uuid)>, network_mtu: usize) {
+    if observations.is_empty() {
+        return;
+    }
+
+    // Every observation is a pair of UUIDs and encodes to the same size, plus a separating comma
+    let entry_len = json::encode(&observations[0]).unwrap().len() + 1;
+    message.ping_observations = Some(Vec::new());
+    let base_len = json::encode(message).unwrap().len();
+
+    observations.truncate(network_mtu.saturating_sub(base_len) / entry_len);
+    message.ping_observations = if observations.is_empty() { None } else { Some(observations) };
+}
+
+fn record_ping_outcome(outcomes: &mut HashMap<SocketAddr, VecDeque<bool>>,
+                       addr: SocketAddr,
+                       acked: bool,
+                       window_size: usize) {
+    if window_size == 0 {
+        return;
+    }
+
+    let window = outcomes.entry(addr).or_insert_with(VecDeque::new);
+    window.push_back(acked);
+
+    while window.len() > window_size {
+        window.pop_front();
+    }
+}
+
+fn wall_clock_ms() -> i64 {
+    let now = time::get_time();
+
+    now.sec * 1000 + (now.nsec / 1_000_000) as i64
+}
+
+fn initial_message_sequence() -> u64 {
+    let now = time::get_time();
+
+    (now.sec as u64).saturating_mul(1_000_000_000).saturating_add(now.nsec as u64)
+}
+
+fn discard_reordered_state_changes(sequences: &mut HashMap<(SocketAddr, Uuid), (u64, u64)>,
+                                   from: SocketAddr,
+                                   sequence: u64,
+                                   state_changes: Vec<StateChange>) -> Vec<StateChange> {
+    let mut accepted = Vec::with_capacity(state_changes.len());
+
+    for state_change in state_changes {
+        let key = (from, state_change.member().host_key());
+        let incarnation = state_change.member().incarnation();
+
+        if let Some(&(last_sequence, last_incarnation)) = sequences.get(&key) {
+            if sequence < last_sequence && incarnation <= last_incarnation {
+                continue;
+            }
+        }
+
+        sequences.insert(key, (sequence, incarnation));
+        accepted.push(state_change);
+    }
+
+    accepted
+}
+
+fn update_rtt_estimate(estimates: &mut HashMap<SocketAddr, Duration>, addr: SocketAddr, sample: Duration) {
+    let estimate = match estimates.get(&addr) {
+        Some(&previous) => (previous * 7 + sample) / 8,
+        None => sample,
+    };
+
+    estimates.insert(addr, estimate);
+}
+
+/// Every live node, including this one, can confirm a suspicion at most once.
+/// When fewer than `required` remain, waiting for more confirmations would
+/// keep the member suspect forever, so the suspicion timeout alone decides.
+fn suspicion_confirmed(sources: usize, required: usize, observers: usize) -> bool {
+    sources >= std::cmp::min(required, std::cmp::max(observers, 1))
+}
+
+fn failure_ratio(outcomes: &VecDeque<bool>) -> f64 {
+    if outcomes.is_empty() {
+        return 0.0;
+    }
+
+    outcomes.iter().filter(|&&acked| !acked).count() as f64 / outcomes.len() as f64
+}
+
+fn pad_message(mut encoded: Vec<u8>, min_size: usize) -> Vec<u8> {
+    while encoded.len() < min_size {
+        encoded.push(b' ');
+    }
+
+    encoded
+}
+
+fn build_probe_message(sender: &Uuid, cluster_key: &Vec<u8>, size: usize, payload_size: usize) -> Message {
+    let mut message = Message {
+        protocol_version: Some(PROTOCOL_VERSION),
+        sender: sender.clone(),
+        cluster_key: cluster_key.clone(),
+        sequence: None,
+        timestamp_ms: Some(wall_clock_ms()),
+        request: Request::MtuProbe(size, String::new()),
+        state_changes: Vec::new(),
+        ping_observations: None,
+        monitor: None,
+    };
+
+    let base_len = json::encode(&message).unwrap().len();
+
+    if base_len + 1 < payload_size {
+        message.request = Request::MtuProbe(size, std::iter::repeat('x').take(payload_size - base_len - 1).collect());
+    }
+
+    message
+}
+
+fn min_network_mtu(config: &ClusterConfig) -> usize {
+    std::cmp::min(MIN_NETWORK_MTU, config.network_mtu)
+}
+
+fn add_to_wait_list(wait_list: &mut WaitList,
+                    wait_addr: &SocketAddr,
+                    notify_addr: &SocketAddr,
+                    expiry: time::Tm,
+                    max_size: usize) -> bool {
+    if wait_list.values().map(|w| w.len()).sum::<usize>() >= max_size {
+        return false;
+    }
+
+    match wait_list.entry(*wait_addr) {
+        Entry::Occupied(mut entry) => { entry.get_mut().push((expiry, notify_addr.clone())); },
+        Entry::Vacant(entry) => { entry.insert(vec![(expiry, notify_addr.clone())]); }
+    };
+
+    true
+}
+
+fn prune_wait_list(wait_list: &mut WaitList, now: time::Tm) -> Vec<(SocketAddr, SocketAddr)> {
+    let mut expired = Vec::new();
+
+    for (target, waiting) in wait_list.iter_mut() {
+        for &(t, remote) in waiting.iter() {
+            if t < now {
+                expired.push((*target, remote));
+            }
+        }
+
+        waiting.retain(|&(t, _)| t >= now);
+    }
+
+    wait_list.retain(|_, waiting| !waiting.is_empty());
+
+    expired
+}
+
+fn expired_hosts_by_timeout(expired: &[(time::Tm, SocketAddr, Vec<StateChange>, Instant)]) -> Vec<SocketAddr> {
+    let mut sorted: Vec<_> = expired.iter().map(|&(t, a, _, _)| (t, a)).collect();
+    sorted.sort_by_key(|&(t, _)| t.to_timespec());
+
+    let mut hosts = Vec::new();
+    for (_, addr) in sorted {
+        if !hosts.contains(&addr) {
+            hosts.push(addr);
+        }
+    }
+
+    hosts
+}
+
+fn remove_potential_seed(seed_queue: &mut Vec<(SocketAddr, u32)>, src_addr: SocketAddr) -> bool {
+    let responded = seed_queue.iter().find(|&&(addr, _)| addr == src_addr).map(|&(_, priority)| priority);
+
+    match responded {
+        Some(priority) => {
+            seed_queue.retain(|&(addr, p)| addr != src_addr && p >= priority);
+            true
+        },
+        None => false,
+    }
+}
+
+fn seeds_to_ping(seed_queue: &[(SocketAddr, u32)], rounds: usize, tier_rounds: usize) -> Vec<SocketAddr> {
+    let mut tiers: Vec<u32> = seed_queue.iter().map(|&(_, priority)| priority).collect();
+    tiers.sort_by(|a, b| b.cmp(a));
+    tiers.dedup();
+
+    let tier_count = match rounds.checked_div(tier_rounds) {
+        Some(fallbacks) => std::cmp::min(fallbacks + 1, tiers.len()),
+        None => tiers.len(),
+    };
+
+    match tiers.get(tier_count.saturating_sub(1)) {
+        Some(&lowest) => seed_queue.iter().filter(|&&(_, p)| p >= lowest).map(|&(addr, _)| addr).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn resolve_cluster_key(config: &ClusterConfig) -> Vec<u8> {
+    match config.cluster_key_provider {
+        Some(ref provider) => provider(),
+        None => config.cluster_key.clone(),
+    }
+}
+
+fn redacted_config(config: &ClusterConfig) -> ClusterConfig {
+    ClusterConfig {
+        cluster_key: Vec::new(),
+        accepted_cluster_keys: Vec::new(),
+        ping_interval: config.ping_interval,
+        network_mtu: config.network_mtu,
+        ping_request_host_count: config.ping_request_host_count,
+        ping_timeout: config.ping_timeout,
+        seed_resolve_interval: config.seed_resolve_interval,
+        direct_ping_timeout: config.direct_ping_timeout,
+        listen_addr: config.listen_addr,
+        tags: config.tags.clone(),
+        max_metadata_bytes: config.max_metadata_bytes,
+        advertise_addr: config.advertise_addr,
+        reject_self_addressed_packets: config.reject_self_addressed_packets,
+        event_flush_interval: config.event_flush_interval,
+        reuse_addr: config.reuse_addr,
+        reuse_port: config.reuse_port,
+        dscp: config.dscp,
+        auto_mtu: config.auto_mtu,
+        checksum: config.checksum,
+        timeline_size: config.timeline_size,
+        assume_new_members_alive: config.assume_new_members_alive,
+        emit_joined_event: config.emit_joined_event,
+        suspicion_timeout: config.suspicion_timeout,
+        adaptive_suspicion: config.adaptive_suspicion,
+        suspicion_confirmations: config.suspicion_confirmations,
+        new_member_grace: config.new_member_grace,
+        wait_list_timeout: config.wait_list_timeout,
+        max_wait_list_size: config.max_wait_list_size,
+        notify_failed_ping_requests: config.notify_failed_ping_requests,
+        wire_overhead: config.wire_overhead,
+        log_full_membership: config.log_full_membership,
+        max_socket_failures: config.max_socket_failures,
+        socket_rebind_attempts: config.socket_rebind_attempts,
+        max_suspicions_per_period: config.max_suspicions_per_period,
+        min_ping_size: config.min_ping_size,
+        receive_buffer_headroom: config.receive_buffer_headroom,
+        ack_window_size: config.ack_window_size,
+        suspicion_failure_ratio: config.suspicion_failure_ratio,
+        prefer_low_rtt_relays: config.prefer_low_rtt_relays,
+        max_indirect_hops: config.max_indirect_hops,
+        relay_ack_window: config.relay_ack_window,
+        expected_members: config.expected_members,
+        shutdown_timeout: config.shutdown_timeout,
+        exit_send_retries: config.exit_send_retries,
+        ordered_state_changes: config.ordered_state_changes,
+        seed_tier_rounds: config.seed_tier_rounds,
+        max_events_per_period: config.max_events_per_period,
+        clock_skew_warning: config.clock_skew_warning,
+        coalesce_state_changes: config.coalesce_state_changes,
+        heartbeat_interval: config.heartbeat_interval,
+        cluster_key_grace_period: config.cluster_key_grace_period,
+        asymmetry_window: config.asymmetry_window,
+        member_list_compaction: config.member_list_compaction,
+        member_history_size: config.member_history_size,
+        initial_tick_delay: config.initial_tick_delay,
+        multicast_discovery: config.multicast_discovery,
+        left_rejoin_policy: config.left_rejoin_policy,
+        max_send_bytes_per_sec: config.max_send_bytes_per_sec,
+        initial_incarnation: config.initial_incarnation,
+        weighted_probe_selection: config.weighted_probe_selection,
+        trusted_identities: config.trusted_identities.clone(),
+        member_reap_after: config.member_reap_after,
+        .. ClusterConfig::default()
+    }
+}
+
+fn is_monitor_event(event: &MemberEvent) -> bool {
+    use MemberEvent::*;
+
+    match *event {
+        MemberJoined(_) | MemberWentUp(_) | MemberWentDown(_) | SocketFailed(_) => true,
+        _ => false,
+    }
+}
+
+fn determine_member_event(previous_state: MemberState, member: Member) -> MemberEvent {
+    use member::MemberState::*;
+    use MemberEvent::*;
+
+    match member.state() {
+        Alive if previous_state == Suspect => SuspicionCleared(member),
+        Alive => MemberWentUp(member),
+        Suspect => MemberSuspectedDown(member),
+        Down => MemberWentDown(member),
+        Left => MemberLeft(member),
+    }
+}
+
+pub fn default_event_priority(event: &MemberEvent) -> u32 {
+    use MemberEvent::*;
+
+    match *event {
+        MemberWentDown(_) | MemberLeft(_) | SocketFailed(_) => 0,
+        MemberSuspectedDown(_) | AsymmetricPartition(_) => 1,
+        MemberJoined(_) | MemberWentUp(_) | SuspicionCleared(_) | Joined(_) | EventsSummarized(_, _)
+            | Heartbeat(_, _) => 2,
+    }
+}
+
+fn sort_member_events(events: &mut Vec<MemberEvent>, priority: &Fn(&MemberEvent) -> u32) {
+    events.sort_by_key(|e| priority(e));
+}
+
+fn summarize_member_event(summarized: &mut Vec<Member>, member: Member) {
+    match summarized.iter().position(|m| m.host_key() == member.host_key()) {
+        Some(index) => summarized[index] = member,
+        None => summarized.push(member),
+    }
+}
+
+fn coalesce_member_event(pending_events: &mut Vec<MemberEvent>, event: MemberEvent) {
+    for pending in pending_events.iter_mut() {
+        if pending.member().host_key() != event.member().host_key() {
+            continue;
+        }
+
+        *pending = match *pending {
+            MemberEvent::MemberJoined(_) => MemberEvent::MemberJoined(event.member().clone()),
+            _ => event,
+        };
+
+        return;
+    }
+
+    pending_events.push(event);
+}
+
+fn enqueue_state_change(state_changes: &mut Vec<StateChange>, members: &[Member]) {
+    'members: for member in members {
+        for state_change in state_changes.iter_mut() {
+            if state_change.member().host_key() == member.host_key() {
+                state_change.update(member.clone());
+                continue 'members;
+            }
+        }
+
+        state_changes.push(StateChange::new(member.clone()));
+    }
+}
+
+fn format_membership(members: &[Member]) -> String {
+    members
+        .iter()
+        .map(|m| format!("{} {} {:?}",
+                         m.host_key().to_hyphenated_string(),
+                         m.remote_host().map_or(String::from("(myself)"), |h| format!("{}", h)),
+                         m.state()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn current_self_state_changes(state_changes: &[StateChange],
+                              myself: &Member,
+                              advertise_addr: Option<SocketAddr>) -> Vec<StateChange> {
+    let myself = match advertise_addr {
+        Some(advertise_addr) => myself.member_by_changing_host(advertise_addr),
+        None => myself.clone(),
+    };
+
+    state_changes
+        .iter()
+        .map(|sc| if sc.member().host_key() == myself.host_key() {
+            StateChange::new(myself.clone())
+        } else {
+            sc.clone()
+        })
+        .collect()
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JoinError::Timeout => formatter.write_str("timed out waiting for a seed node to respond"),
+            JoinError::AllSeedsRejected => formatter.write_str("all seed nodes rejected the join"),
+            JoinError::BindFailed(kind) => write!(formatter, "could not bind the cluster socket: {:?}", kind),
+        }
+    }
+}
+
+impl error::Error for JoinError {
+    fn description(&self) -> &str {
+        match *self {
+            JoinError::Timeout => "timed out waiting for a seed node to respond",
+            JoinError::AllSeedsRejected => "all seed nodes rejected the join",
+            JoinError::BindFailed(_) => "could not bind the cluster socket",
+        }
+    }
+}
+
+impl Decodable for EncSocketAddr {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        match d.read_str() {
+            Ok(s) => match FromStr::from_str(&s) {
+                Ok(addr) => Ok(EncSocketAddr(addr)),
+                Err(e) => Err(d.error(&format!("{:?}", e))),
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Encodable for EncSocketAddr {
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        let &EncSocketAddr(addr) = self;
+        format!("{}", addr).encode(e)
+    }
+}
+
+impl Request {
+    fn is_time_sensitive(&self) -> bool {
+        match *self {
+            Request::Ack | Request::AckHost(_) | Request::PingRequest(_) | Request::PingRequestWithHops(_, _)
+                | Request::PingRequestFailed(_) | Request::MtuProbeAck(_) | Request::Reject => true,
+            _ => false,
+        }
+    }
+
+    fn kind(&self) -> RequestKind {
+        match *self {
+            Request::Ping => RequestKind::Ping,
+            Request::Ack => RequestKind::Ack,
+            Request::PingRequest(_) | Request::PingRequestWithHops(_, _) => RequestKind::PingRequest,
+            Request::AckHost(_) => RequestKind::AckHost,
+            Request::PingRequestFailed(_) => RequestKind::PingRequestFailed,
+            Request::MtuProbe(_, _) => RequestKind::MtuProbe,
+            Request::MtuProbeAck(_) => RequestKind::MtuProbeAck,
+            Request::Reject => RequestKind::Reject,
+            Request::Sync => RequestKind::Sync,
+            Request::SyncAck => RequestKind::SyncAck,
+        }
+    }
+}
+
+impl Message {
+    fn summary(&self) -> MessageSummary {
+        MessageSummary {
+            sender: self.sender,
+            kind: self.request.kind(),
+            sequence: self.sequence,
+            state_changes: self.state_changes.len(),
+        }
+    }
+}
+
+impl EncSocketAddr {
+    fn from_addr(addr: &SocketAddr) -> Self {
+        EncSocketAddr(addr.clone())
+    }
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            cluster_key: "default".as_bytes().to_vec(),
+            accepted_cluster_keys: Vec::new(),
+            ping_interval: Duration::seconds(1),
+            network_mtu: 512,
+            ping_request_host_count: 3,
+            ping_timeout: Duration::seconds(3),
+            direct_ping_timeout: None,
+            seed_resolve_interval: None,
+            listen_addr: "127.0.0.1:2552".to_socket_addrs().unwrap().next().unwrap(),
+            tags: HashSet::new(),
+            max_metadata_bytes: 1024,
+            advertise_addr: None,
+            reject_self_addressed_packets: true,
+            conflict_resolver: Box::new(DefaultConflictResolver),
+            event_flush_interval: None,
+            event_priority: Box::new(default_event_priority),
+            reuse_addr: false,
+            reuse_port: false,
+            dscp: None,
+            auto_mtu: false,
+            checksum: false,
+            packet_tracer: None,
+            timeline_size: 0,
+            assume_new_members_alive: true,
+            emit_joined_event: false,
+            self_state_hook: None,
+            suspicion_timeout: Duration::seconds(3),
+            adaptive_suspicion: false,
+            suspicion_confirmations: 1,
+            new_member_grace: Duration::zero(),
+            wait_list_timeout: Duration::seconds(10),
+            max_wait_list_size: 1024,
+            notify_failed_ping_requests: false,
+            wire_overhead: 0,
+            log_full_membership: false,
+            max_socket_failures: 3,
+            socket_rebind_attempts: 3,
+            max_suspicions_per_period: None,
+            min_ping_size: 0,
+            receive_buffer_headroom: 1024,
+            ack_window_size: 0,
+            suspicion_failure_ratio: 0.5,
+            prefer_low_rtt_relays: false,
+            max_indirect_hops: 0,
+            relay_ack_window: None,
+            expected_members: 0,
+            identity: None,
+            shutdown_timeout: Duration::seconds(5),
+            exit_send_retries: 3,
+            ordered_state_changes: false,
+            seed_tier_rounds: 3,
+            max_events_per_period: None,
+            clock_skew_warning: Duration::seconds(1),
+            coalesce_state_changes: false,
+            heartbeat_interval: None,
+            cluster_key_provider: None,
+            cluster_key_grace_period: Duration::seconds(30),
+            asymmetry_window: None,
+            member_list_compaction: None,
+            event_sink: None,
+            member_history_size: 0,
+            initial_tick_delay: None,
+            multicast_discovery: None,
+            left_rejoin_policy: LeftRejoinPolicy::Reject,
+            max_send_bytes_per_sec: None,
+            initial_incarnation: 0,
+            weighted_probe_selection: false,
+            trusted_identities: None,
+            member_reap_after: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    use mio;
+    use rustc_serialize::json;
+    use time;
+    use time::Duration;
+    use uuid::Uuid;
+
+    use checksum;
+    use member::{DefaultConflictResolver, Member, MemberState, StateChange};
+    use memberlist::MemberList;
+    use stats::PacketCounters;
+    use super::{add_to_wait_list, build_message, build_message_with_remainder, current_self_state_changes,
+                default_event_priority,
+                drop_self_addressed_packet, expired_hosts_by_timeout, format_membership,
+                attach_ping_observations, record_ping_observation, recent_ping_observations,
+                discard_reordered_state_changes, remove_potential_seed, seeds_to_ping, wall_clock_ms,
+                announced_seed_addr, sign_announcement, verify_announcement, enforce_metadata_limit, failure_ratio, suspicion_confirmed, is_monitor_event, redacted_config, resolve_cluster_key, InternalRequest, Message, update_rtt_estimate, packet_overhead, pad_message, record_ping_outcome, prune_wait_list, sort_member_events, ClusterConfig, MemberEvent, Request,
+                Cluster, DropReason, EncSocketAddr, JoinError, MulticastConfig, State, TargetedRequest};
+
+    #[test]
+    fn test_message_summary_describes_the_message() {
+        let sender = Uuid::new_v4();
+        let message = build_message(&sender, &Vec::new(), 7, Request::Ack, Vec::new(), 512).unwrap();
+
+        assert_eq!(format!("{}", message.summary()),
+                   format!("Ack from {} (sequence 7, 0 state changes)", sender.to_hyphenated_string()));
+    }
+
+    #[test]
+    fn test_announcements_are_authenticated_by_the_cluster_key() {
+        let key = "secret".as_bytes().to_vec();
+        let other_key = "other".as_bytes().to_vec();
+        let addr: SocketAddr = FromStr::from_str("10.0.0.5:2552").unwrap();
+        let now = 1_000_000;
+
+        let announcement = sign_announcement(&key, Uuid::new_v4(), addr, now);
+        assert!(verify_announcement(&announcement, &[&key], now));
+        assert!(verify_announcement(&announcement, &[&other_key, &key], now));
+        assert!(!verify_announcement(&announcement, &[&other_key], now));
+        assert!(!verify_announcement(&announcement, &[&key], now + 120_000));
+
+        let encoded = json::encode(&announcement).unwrap();
+        assert!(!encoded.contains("secret"));
+
+        let mut redirected = announcement.clone();
+        redirected.addr = EncSocketAddr(FromStr::from_str("10.0.0.66:2552").unwrap());
+        assert!(!verify_announcement(&redirected, &[&key], now));
+    }
+
+    #[test]
+    fn test_received_announcements_become_seeds() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            cluster_key: "secret".as_bytes().to_vec(),
+            multicast_discovery: Some(MulticastConfig {
+                group: FromStr::from_str("239.255.42.99:0").unwrap(),
+                announce_interval: Duration::seconds(1),
+            }),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let port = state.discovery_socket.as_ref().unwrap().local_addr().unwrap().port();
+        let discovery_addr: SocketAddr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+
+        let announcer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let announced: SocketAddr = FromStr::from_str("127.0.0.1:3201").unwrap();
+        let forged: SocketAddr = FromStr::from_str("127.0.0.1:3202").unwrap();
+
+        for &(key, addr) in &[("secret", announced), ("guessed", forged)] {
+            let announcement = sign_announcement(key.as_bytes(), Uuid::new_v4(), addr, super::wall_clock_ms());
+            announcer.send_to(json::encode(&announcement).unwrap().as_bytes(), discovery_addr).unwrap();
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        state.receive_announcement();
+
+        assert!(state.seed_queue.iter().any(|&(addr, _)| addr == announced));
+        assert!(!state.seed_queue.iter().any(|&(addr, _)| addr == forged));
+    }
+
+    #[test]
+    fn test_announced_seed_addr_substitutes_sender_ip_for_wildcard() {
+        let src_addr = SocketAddr::from_str("10.0.0.5:7946").unwrap();
+
+        assert_eq!(announced_seed_addr(SocketAddr::from_str("0.0.0.0:2552").unwrap(), src_addr),
+                   SocketAddr::from_str("10.0.0.5:2552").unwrap());
+        assert_eq!(announced_seed_addr(SocketAddr::from_str("10.0.0.9:2552").unwrap(), src_addr),
+                   SocketAddr::from_str("10.0.0.9:2552").unwrap());
+    }
+
+    #[test]
+    fn test_unreachable_targets_do_not_leak_wait_list_entries() {
+        let mut wait_list = HashMap::new();
+        let target: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let requester: SocketAddr = FromStr::from_str("127.0.0.1:2562").unwrap();
+        let now = time::now_utc();
+
+        assert!(add_to_wait_list(&mut wait_list, &target, &requester, now + Duration::seconds(1), 10));
+        assert!(prune_wait_list(&mut wait_list, now).is_empty());
+        assert_eq!(wait_list.len(), 1);
+
+        let expired = prune_wait_list(&mut wait_list, now + Duration::seconds(2));
+
+        assert_eq!(expired, vec![(target, requester)]);
+        assert!(wait_list.is_empty());
+    }
+
+    #[test]
+    fn test_wait_list_is_bounded() {
+        let mut wait_list = HashMap::new();
+        let target: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let requester: SocketAddr = FromStr::from_str("127.0.0.1:2562").unwrap();
+        let expiry = time::now_utc() + Duration::seconds(1);
+
+        assert!(add_to_wait_list(&mut wait_list, &target, &requester, expiry, 1));
+        assert!(!add_to_wait_list(&mut wait_list, &target, &requester, expiry, 1));
+    }
+
+    #[test]
+    fn test_identity_rejections_are_counted_as_a_drop_reason() {
+        let mut counters = PacketCounters::default();
+
+        counters.record_identity_rejection();
+
+        assert_eq!(counters.identity_rejections, 1);
+        assert_eq!(counters.drop_counts.get(&DropReason::IdentityRejected), Some(&1));
+    }
+
+    #[test]
+    fn test_self_addressed_packets_are_dropped_and_counted() {
+        let config = ClusterConfig::default();
+        let mut counters = PacketCounters::default();
+        let local_addr: SocketAddr = FromStr::from_str("127.0.0.1:40000").unwrap();
+        let peer: SocketAddr = FromStr::from_str("127.0.0.1:2562").unwrap();
+
+        assert!(drop_self_addressed_packet(&local_addr, None, &mut counters, &local_addr));
+        assert_eq!(counters.packets_dropped, 1);
+        assert_eq!(counters.drop_counts.get(&DropReason::SelfAddressed), Some(&1));
+
+        assert!(!drop_self_addressed_packet(&local_addr, None, &mut counters, &peer));
+        assert!(!drop_self_addressed_packet(&local_addr, None, &mut counters, &config.listen_addr));
+        assert!(drop_self_addressed_packet(&local_addr, Some(peer), &mut counters, &peer));
+        assert_eq!(counters.packets_dropped, 2);
+    }
+
+    #[test]
+    fn test_oversized_ack_host_is_not_built() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+        member.set_tags((0..100).map(|i| format!("tag-{}", i)).collect());
+
+        let state_changes = vec![StateChange::new(member.clone())];
+        let request = Request::AckHost(member);
+
+        assert!(build_message(&Uuid::new_v4(), &vec![], 0, request.clone(), state_changes.clone(), 512).is_none());
+
+        let message = build_message(&Uuid::new_v4(), &vec![], 0, request, state_changes, 4096).unwrap();
+        assert!(json::encode(&message).unwrap().len() < 4096);
+    }
+
+    #[test]
+    fn test_wire_overhead_is_reserved_in_mtu_budget() {
+        let config = ClusterConfig {
+            checksum: true,
+            wire_overhead: 28,
+            .. ClusterConfig::default()
+        };
+
+        let state_changes: Vec<_> = (0..50)
+            .map(|i| {
+                let addr = FromStr::from_str(&format!("127.0.0.1:{}", 3000 + i)).unwrap();
+                StateChange::new(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive))
+            })
+            .collect();
+
+        let message = build_message(&Uuid::new_v4(), &config.cluster_key, 0, Request::Ping, state_changes,
+                                    config.network_mtu - packet_overhead(&config)).unwrap();
+        let encoded = checksum::append_checksum(&json::encode(&message).unwrap().into_bytes());
+
+        assert!(!message.state_changes.is_empty());
+        assert!(encoded.len() + config.wire_overhead < config.network_mtu);
+    }
+
+    #[test]
+    fn test_membership_log_line_lists_every_member() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 0, MemberState::Suspect);
+        let myself = Member::myself(Uuid::new_v4());
+
+        let line = format_membership(&[peer.clone(), myself.clone()]);
+
+        assert_eq!(line, format!("{} 127.0.0.1:2552 Suspect, {} (myself) Alive",
+                                 peer.host_key().to_hyphenated_string(),
+                                 myself.host_key().to_hyphenated_string()));
+    }
+
+    #[test]
+    fn test_server_socket_is_rebound_on_the_same_address() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (mut event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let local_addr = state.local_addr;
+
+        state.rebind_server_socket(&mut event_loop).unwrap();
+
+        assert_eq!(state.server_socket.local_addr().unwrap(), local_addr);
+    }
+
+    #[test]
+    fn test_ping_outcomes_use_a_sliding_window() {
+        let mut outcomes = HashMap::new();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+
+        for &acked in &[false, false, true, true, true, false] {
+            record_ping_outcome(&mut outcomes, addr, acked, 4);
+        }
+
+        assert_eq!(outcomes[&addr].len(), 4);
+        assert_eq!(failure_ratio(&outcomes[&addr]), 0.25);
+    }
+
+    #[test]
+    fn test_suspicion_falls_back_to_the_timeout_without_enough_observers() {
+        assert!(!suspicion_confirmed(1, 3, 5));
+        assert!(suspicion_confirmed(3, 3, 5));
+
+        assert!(!suspicion_confirmed(1, 3, 2));
+        assert!(suspicion_confirmed(2, 3, 2));
+        assert!(suspicion_confirmed(1, 3, 0));
+    }
+
+    #[test]
+    fn test_seed_tiers_fall_back_after_unanswered_rounds() {
+        let local: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let local2: SocketAddr = FromStr::from_str("127.0.0.1:2553").unwrap();
+        let remote: SocketAddr = FromStr::from_str("10.0.0.1:2552").unwrap();
+        let mut seeds = vec![(remote, 0), (local, 10), (local2, 10)];
+
+        assert_eq!(seeds_to_ping(&seeds, 0, 2), vec![local, local2]);
+        assert_eq!(seeds_to_ping(&seeds, 1, 2), vec![local, local2]);
+        assert_eq!(seeds_to_ping(&seeds, 2, 2), vec![remote, local, local2]);
+
+        assert!(remove_potential_seed(&mut seeds, local));
+        assert_eq!(seeds, vec![(local2, 10)]);
+
+        assert!(!remove_potential_seed(&mut seeds, remote));
+    }
+
+    #[test]
+    fn test_expired_hosts_are_ordered_by_timeout() {
+        let first: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let second: SocketAddr = FromStr::from_str("127.0.0.1:2553").unwrap();
+        let third: SocketAddr = FromStr::from_str("127.0.0.1:2554").unwrap();
+        let now = time::now_utc();
+        let sent_at = Instant::now();
+
+        let expired = vec![
+            (now - Duration::milliseconds(100), third, Vec::new(), sent_at),
+            (now - Duration::milliseconds(300), first, Vec::new(), sent_at),
+            (now - Duration::milliseconds(50), first, Vec::new(), sent_at),
+            (now - Duration::milliseconds(200), second, Vec::new(), sent_at),
+        ];
+
+        assert_eq!(expired_hosts_by_timeout(&expired), vec![first, second, third]);
+    }
+
+    #[test]
+    fn test_rate_cap_defers_gossip_but_not_acks() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            max_send_bytes_per_sec: Some(1000),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = receiver.local_addr().unwrap();
+        let started = std::time::Instant::now();
+
+        for _ in 0..50 {
+            state.process_request(TargetedRequest { request: Request::Ping, target: target });
+        }
+
+        let elapsed = started.elapsed();
+        let allowance = 1000.0 + 1000.0 * (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9);
+        assert!(state.counters.bytes_sent as f64 <= allowance, "sent {} bytes", state.counters.bytes_sent);
+        assert!(!state.deferred_sends.is_empty());
+
+        let rate_limited = state.counters.rate_limited_sends;
+        assert_eq!(rate_limited as usize, state.deferred_sends.len());
+
+        let packets_sent = state.counters.packets_sent;
+        for _ in 0..5 {
+            state.process_request(TargetedRequest { request: Request::Ack, target: target });
+        }
+        assert_eq!(state.counters.packets_sent, packets_sent + 5);
+
+        state.retry_deferred_sends();
+        assert_eq!(state.counters.rate_limited_sends, rate_limited);
+    }
+
+    #[test]
+    fn test_unbound_server_socket_is_recovered_on_a_later_attempt() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (mut event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let local_addr = state.local_addr;
+
+        let _ = event_loop.deregister(&state.server_socket);
+        state.server_socket = super::unbound_socket(&local_addr).unwrap();
+        state.socket_unbound = true;
+
+        let blocker = UdpSocket::bind(local_addr).unwrap();
+        state.recover_socket(&mut event_loop);
+        assert!(state.socket_unbound);
+
+        drop(blocker);
+        state.recover_socket(&mut event_loop);
+        assert!(!state.socket_unbound);
+        assert_eq!(state.server_socket.local_addr().unwrap(), local_addr);
+    }
+
+    #[test]
+    fn test_reordered_state_changes_are_discarded() {
+        let mut sequences = HashMap::new();
+        let from: SocketAddr = FromStr::from_str("127.0.0.1:2562").unwrap();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let host_key = Uuid::new_v4();
+        let suspect = StateChange::new(Member::new(host_key, addr, 1, MemberState::Suspect));
+        let stale = StateChange::new(Member::new(host_key, addr, 1, MemberState::Alive));
+        let refuted = StateChange::new(Member::new(host_key, addr, 2, MemberState::Alive));
+
+        assert_eq!(discard_reordered_state_changes(&mut sequences, from, 10, vec![suspect]).len(), 1);
+        assert!(discard_reordered_state_changes(&mut sequences, from, 9, vec![stale.clone()]).is_empty());
+        assert_eq!(discard_reordered_state_changes(&mut sequences, from, 8, vec![refuted]).len(), 1);
+        assert_eq!(discard_reordered_state_changes(&mut sequences, from, 11, vec![stale]).len(), 1);
+    }
+
+    #[test]
+    fn test_rtt_is_measured_from_the_recorded_send_instant() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+
+        // A deadline an hour out would imply a negative RTT if the send time were derived from it
+        let deadline = time::now_utc() + Duration::hours(1);
+        state.pending_responses.push((deadline, addr, Vec::new(), Instant::now()));
+
+        state.ack_response(addr);
+
+        let rtt = state.rtt_estimates[&addr];
+        assert!(rtt >= Duration::zero());
+        assert!(rtt < Duration::seconds(1));
+    }
+
+    #[test]
+    fn test_clock_offset_is_measured_from_the_recorded_send_instant() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+
+        let deadline = time::now_utc() + Duration::hours(1);
+        state.pending_responses.push((deadline, addr, Vec::new(), Instant::now()));
+
+        state.record_clock_offset(addr, wall_clock_ms());
+
+        let offset = state.members.member_for(&addr).unwrap().clock_offset().unwrap();
+        assert!(offset.num_milliseconds().abs() < 1000);
+    }
+
+    #[test]
+    fn test_rtt_estimate_is_smoothed() {
+        let mut estimates = HashMap::new();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+
+        update_rtt_estimate(&mut estimates, addr, Duration::milliseconds(80));
+        assert_eq!(estimates[&addr], Duration::milliseconds(80));
+
+        update_rtt_estimate(&mut estimates, addr, Duration::milliseconds(160));
+        assert_eq!(estimates[&addr], Duration::milliseconds(90));
+    }
+
+    #[test]
+    fn test_padded_ping_decodes() {
+        let message = build_message(&Uuid::new_v4(), &vec![], 0, Request::Ping, vec![], 512).unwrap();
+        let encoded = pad_message(json::encode(&message).unwrap().into_bytes(), 400);
+
+        assert_eq!(encoded.len(), 400);
+
+        let decoded: super::Message = json::decode(&*String::from_utf8_lossy(&encoded)).unwrap();
+        assert!(decoded.request == Request::Ping);
+    }
+
+    #[test]
+    fn test_flooding_the_internal_channel_does_not_panic() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+        for _ in 0..100000 {
+            state.send_internal(InternalRequest::Flush);
+        }
+
+        assert!(state.counters.internal_requests_dropped > 0);
+    }
+
+    #[test]
+    fn test_dropping_a_cluster_with_a_dead_event_loop_completes() {
+        let event_loop: mio::EventLoop<State> = mio::EventLoop::new().unwrap();
+        let (_event_tx, event_rx) = channel();
+        let cluster = Cluster {
+            events: event_rx,
+            comm: event_loop.channel(),
+            local_addr: FromStr::from_str("127.0.0.1:2552").unwrap(),
+            stopped: false,
+            shutdown_timeout: Duration::milliseconds(100),
+            exit_send_retries: 3,
+        };
+
+        drop(event_loop);
+
+        let started = time::now_utc();
+        drop(cluster);
+
+        assert!(time::now_utc() - started < Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_dropping_a_cluster_whose_event_loop_never_acknowledges_exit_times_out() {
+        let event_loop: mio::EventLoop<State> = mio::EventLoop::new().unwrap();
+        let (_event_tx, event_rx) = channel();
+        let cluster = Cluster {
+            events: event_rx,
+            comm: event_loop.channel(),
+            local_addr: FromStr::from_str("127.0.0.1:2552").unwrap(),
+            stopped: false,
+            shutdown_timeout: Duration::milliseconds(100),
+            exit_send_retries: 3,
+        };
+
+        // The loop stays alive but never runs, so the exit request is queued
+        // and the acknowledgement never arrives
+        let started = time::now_utc();
+        drop(cluster);
+        let elapsed = time::now_utc() - started;
+
+        assert!(elapsed >= Duration::milliseconds(100));
+        assert!(elapsed < Duration::seconds(2));
+
+        drop(event_loop);
+    }
+
+    #[test]
+    fn test_start_and_join_reports_failures() {
+        let occupied = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClusterConfig {
+            listen_addr: occupied.local_addr().unwrap(),
+            .. ClusterConfig::default()
+        };
+
+        let result = Cluster::start_and_join(Uuid::new_v4(), config, Vec::new(), Duration::seconds(1));
+        assert_eq!(result.err(), Some(JoinError::BindFailed(io::ErrorKind::AddrInUse)));
+
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+
+        let result = Cluster::start_and_join(Uuid::new_v4(), config, Vec::new(), Duration::seconds(1));
+        assert_eq!(result.err(), Some(JoinError::AllSeedsRejected));
+    }
+
+    #[test]
+    fn test_monitor_pings_do_not_add_the_monitor_as_a_member() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let key = state.cluster_key.clone();
+        let monitor: SocketAddr = FromStr::from_str("127.0.0.1:3301").unwrap();
+        let peer: SocketAddr = FromStr::from_str("127.0.0.1:3302").unwrap();
+
+        let mut message = build_message(&Uuid::new_v4(), &key, 1, Request::Ping, Vec::new(), 512).unwrap();
+        message.monitor = Some(true);
+        state.respond_to_message(monitor, message);
+        assert!(state.members.member_for(&monitor).is_none());
+
+        let message = build_message(&Uuid::new_v4(), &key, 1, Request::Ping, Vec::new(), 512).unwrap();
+        state.respond_to_message(peer, message);
+        assert!(state.members.member_for(&peer).is_some());
+    }
+
+    #[test]
+    fn test_concurrent_joins_are_all_answered() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let seed: SocketAddr = FromStr::from_str("127.0.0.1:2599").unwrap();
+        let deadline = time::now_utc() + Duration::seconds(10);
+        let (first_tx, first_rx) = channel();
+        let (second_tx, second_rx) = channel();
+
+        state.process_internal_request(InternalRequest::Join(vec![seed], deadline, first_tx));
+        state.process_internal_request(InternalRequest::Join(vec![seed], deadline, second_tx));
+
+        state.seed_rejected(seed);
+        assert!(first_rx.try_recv().is_err());
+
+        state.seed_acked(seed);
+        assert_eq!(first_rx.try_recv().unwrap(), Ok(()));
+        assert_eq!(second_rx.try_recv().unwrap(), Ok(()));
+        assert!(state.join_waiters.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_are_limited_per_address_and_period() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addrs: Vec<SocketAddr> = (3100..3120)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        assert!(state.should_reject(addrs[0]));
+        assert!(!state.should_reject(addrs[0]));
+        assert_eq!(addrs.iter().filter(|&&addr| state.should_reject(addr)).count(), super::MAX_REJECTS_PER_PERIOD - 1);
+    }
+
+    #[test]
+    fn test_dropping_a_cluster_with_a_full_channel_completes() {
+        let event_loop: mio::EventLoop<State> = mio::EventLoop::new().unwrap();
+        let comm = event_loop.channel();
+
+        while comm.send(InternalRequest::Flush).is_ok() {}
+
+        let (_event_tx, event_rx) = channel();
+        let cluster = Cluster {
+            events: event_rx,
+            comm: comm,
+            local_addr: FromStr::from_str("127.0.0.1:2552").unwrap(),
+            stopped: false,
+            shutdown_timeout: Duration::milliseconds(100),
+            exit_send_retries: 2,
+        };
+
+        let started = time::now_utc();
+        drop(cluster);
+
+        assert!(time::now_utc() - started < Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_effective_config_redacts_cluster_keys() {
+        let config = ClusterConfig {
+            cluster_key: "secret".as_bytes().to_vec(),
+            accepted_cluster_keys: vec!["old-secret".as_bytes().to_vec()],
+            ping_interval: Duration::milliseconds(250),
+            max_events_per_period: Some(10),
+            .. ClusterConfig::default()
+        };
+
+        let redacted = redacted_config(&config);
+
+        assert!(redacted.cluster_key.is_empty());
+        assert!(redacted.accepted_cluster_keys.is_empty());
+        assert_eq!(redacted.ping_interval, Duration::milliseconds(250));
+        assert_eq!(redacted.max_events_per_period, Some(10));
+    }
+
+    #[test]
+    fn test_state_changes_from_several_peers_are_coalesced() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            coalesce_state_changes: true,
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let host_key = Uuid::new_v4();
+        state.members.add_member(Member::new(host_key, addr, 0, MemberState::Alive));
+
+        let peers: Vec<SocketAddr> = (2562..2565)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+        let reports = vec![MemberState::Suspect, MemberState::Down, MemberState::Down];
+
+        for (peer, reported) in peers.into_iter().zip(reports) {
+            let change = StateChange::new(Member::new(host_key, addr, 0, reported));
+            state.apply_state_changes(vec![change], peer);
+        }
+
+        assert!(state.queued_events.is_empty());
+
+        state.flush_coalesced_changes();
+
+        assert_eq!(state.queued_events.len(), 1);
+        match state.queued_events[0] {
+            MemberEvent::MemberWentDown(ref m) => assert_eq!(m.host_key(), host_key),
+            ref other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ping_requests_are_forwarded_along_a_chain_until_the_hops_run_out() {
+        let addrs: Vec<SocketAddr> = (2572..2576)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+        let (origin, first, second, target) = (addrs[0], addrs[1], addrs[2], addrs[3]);
+
+        let mut relays = Vec::new();
+        for neighbours in vec![vec![origin, second, target], vec![first, target]] {
+            let config = ClusterConfig {
+                listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+                .. ClusterConfig::default()
+            };
+            let (event_tx, _event_rx) = channel();
+            let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+            for addr in neighbours {
+                state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+            }
+
+            relays.push(state);
+        }
+
+        let forward = relays[0].forward_ping_request(origin, target, 1).unwrap();
+        assert_eq!(forward.target, second);
+        assert_eq!(forward.request, Request::PingRequest(EncSocketAddr::from_addr(&target)));
+
+        assert!(relays[1].forward_ping_request(first, target, 0).is_none());
+    }
+
+    #[test]
+    fn test_forwarded_ping_requests_ack_back_to_the_origin() {
+        let addrs: Vec<SocketAddr> = (2582..2586)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+        let (origin, first, second, target) = (addrs[0], addrs[1], addrs[2], addrs[3]);
+
+        let mut relays = Vec::new();
+        for neighbours in vec![vec![origin, second, target], vec![first, target]] {
+            let config = ClusterConfig {
+                listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+                .. ClusterConfig::default()
+            };
+            let (event_tx, _event_rx) = channel();
+            let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+            for addr in neighbours {
+                state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+            }
+
+            relays.push(state);
+        }
+
+        let ping = relays[0].accept_ping_request(origin, target, 1).unwrap();
+        assert_eq!((ping.target, ping.request), (target, Request::Ping));
+
+        // Nothing is forwarded until the first relay's own ping goes unanswered
+        assert_eq!(relays[0].pending_forwards.len(), 1);
+        let forwards = relays[0].forward_unanswered_ping_requests(target);
+        assert_eq!(forwards.len(), 1);
+        assert_eq!(forwards[0].target, second);
+        assert_eq!(forwards[0].request, Request::PingRequest(EncSocketAddr::from_addr(&target)));
+        assert!(relays[0].forward_unanswered_ping_requests(target).is_empty());
+
+        relays[1].accept_ping_request(first, target, 0).unwrap();
+        assert!(relays[1].pending_forwards.is_empty());
+
+        let acks = relays[1].acks_for_waiting_hosts(target);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].target, first);
+
+        let acks = relays[0].acks_for_waiting_hosts(target);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].target, origin);
+        match acks[0].request {
+            Request::AckHost(ref member) => assert_eq!(member.remote_host(), Some(target)),
+            ref other => panic!("unexpected request {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_answered_ping_requests_are_not_forwarded() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+        let origin: SocketAddr = FromStr::from_str("127.0.0.1:2590").unwrap();
+        let target: SocketAddr = FromStr::from_str("127.0.0.1:2591").unwrap();
+        state.members.add_member(Member::new(Uuid::new_v4(), target, 0, MemberState::Alive));
+
+        state.accept_ping_request(origin, target, 2).unwrap();
+        assert_eq!(state.acks_for_waiting_hosts(target).len(), 1);
+        assert!(state.forward_unanswered_ping_requests(target).is_empty());
+    }
+
+    #[test]
+    fn test_heartbeat_carries_the_current_view() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            heartbeat_interval: Some(Duration::milliseconds(100)),
+            max_events_per_period: Some(0),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+
+        state.emit_heartbeat();
+
+        match event_rx.try_recv().unwrap() {
+            (_, MemberEvent::Heartbeat(ref myself, ref view)) => {
+                assert!(myself.is_myself());
+                assert_eq!(view.len(), 2);
+            },
+            (_, other) => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cluster_key_provider_overrides_the_configured_key() {
+        let config = ClusterConfig {
+            cluster_key: "configured".as_bytes().to_vec(),
+            .. ClusterConfig::default()
+        };
+        assert_eq!(resolve_cluster_key(&config), "configured".as_bytes().to_vec());
+
+        let config = ClusterConfig {
+            cluster_key: Vec::new(),
+            cluster_key_provider: Some(Box::new(|| "from-vault".as_bytes().to_vec())),
+            .. ClusterConfig::default()
+        };
+        assert_eq!(resolve_cluster_key(&config), "from-vault".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_unconfirmed_new_members_are_not_gossiped_as_suspect() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            assume_new_members_alive: false,
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let from: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let heard_of = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3002").unwrap(), 0, MemberState::Alive);
+        let suspected = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3003").unwrap(), 0, MemberState::Suspect);
+
+        state.apply_state_changes(vec![StateChange::new(heard_of.clone()), StateChange::new(suspected.clone())], from);
+
+        assert_eq!(state.members.member_by_host_key(&heard_of.host_key()).unwrap().state(), MemberState::Suspect);
+        assert!(state.state_changes.iter().all(|sc| sc.member().host_key() != heard_of.host_key()));
+        assert!(state.state_changes.iter().any(|sc| sc.member().host_key() == suspected.host_key()));
+    }
+
+    #[test]
+    fn test_suspicion_cap_holds_across_the_protocol_period() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            max_suspicions_per_period: Some(1),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let expired = time::now_utc() - Duration::seconds(1);
+
+        for port in 3001..3003 {
+            let addr: SocketAddr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+            state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+            state.pending_responses.push((expired, addr, Vec::new(), Instant::now()));
+        }
+
+        state.prune_timed_out_responses();
+        state.prune_timed_out_responses();
+        assert_eq!(state.members.count_in_state(MemberState::Suspect), 1);
+        assert_eq!(state.pending_responses.len(), 1);
+
+        state.escalations_this_period = 0;
+        state.prune_timed_out_responses();
+        assert_eq!(state.members.count_in_state(MemberState::Suspect), 2);
+    }
+
+    #[test]
+    fn test_state_changes_that_do_not_fit_are_returned() {
+        let state_changes: Vec<_> = (3001..3101)
+            .map(|port| {
+                let addr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+                StateChange::new(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive))
+            })
+            .collect();
+
+        let (message, remainder) = build_message_with_remainder(&Uuid::new_v4(), &vec![], 0, Request::Sync,
+                                                                state_changes.clone(), 512).unwrap();
+
+        assert!(!message.state_changes.is_empty());
+        assert!(!remainder.is_empty());
+        assert_eq!(message.state_changes.len() + remainder.len(), state_changes.len());
+        assert_eq!(remainder[0].member().host_key(), state_changes[message.state_changes.len()].member().host_key());
+    }
+
+    #[test]
+    fn test_full_sync_is_split_across_datagrams() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let target: SocketAddr = FromStr::from_str("127.0.0.1:2999").unwrap();
+
+        for port in 3001..3101 {
+            let addr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+            state.members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+        }
+
+        state.send_request(TargetedRequest { request: Request::Sync, target: target }, false);
+        let mut remaining = state.sync_remainders[&target].len();
+        let mut datagrams = 1;
+
+        while state.sync_remainders.contains_key(&target) {
+            state.send_request(TargetedRequest { request: Request::SyncAck, target: target }, false);
+            datagrams += 1;
+
+            let now_remaining = state.sync_remainders.get(&target).map_or(0, |r| r.len());
+            assert!(now_remaining < remaining);
+            remaining = now_remaining;
+        }
+
+        assert!(datagrams > 2);
+    }
+
+    #[test]
+    fn test_replacing_seeds_keeps_seed_hosts_and_resolved_hosts_become_seeds() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let seed: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let resolved: SocketAddr = FromStr::from_str("127.0.0.1:3002").unwrap();
+        state.seed_hosts.push(String::from("seed.example.com:2552"));
+
+        state.process_internal_request(InternalRequest::ReplaceSeeds(vec![seed]));
+        assert_eq!(state.seed_hosts, vec![String::from("seed.example.com:2552")]);
+
+        state.process_internal_request(InternalRequest::SeedHostsResolved(vec![resolved]));
+        assert_eq!(state.seed_queue, vec![(seed, 0), (resolved, 0)]);
+    }
+
+    #[test]
+    fn test_cleared_suspicion_asks_the_member_to_refute() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 4, MemberState::Suspect);
+        state.members.add_member(peer.clone());
+
+        state.clear_suspicion(peer.host_key());
+
+        assert_eq!(state.members.member_by_host_key(&peer.host_key()).unwrap().state(), MemberState::Alive);
+        assert!(state.state_changes.iter().all(|sc| sc.member().host_key() != peer.host_key()));
+
+        let &(_, target, ref sent, _) = state.pending_responses.last().unwrap();
+        assert_eq!(target, addr);
+        assert!(sent.iter().any(|sc| sc.member().host_key() == peer.host_key()
+                                   && sc.member().state() == MemberState::Suspect));
+    }
+
+    #[test]
+    fn test_cleared_suspicion_survives_stale_rumours() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let peer = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3001").unwrap(), 4, MemberState::Suspect);
+        let gossiper = FromStr::from_str("127.0.0.1:3002").unwrap();
+        state.members.add_member(peer.clone());
+
+        state.clear_suspicion(peer.host_key());
+        state.apply_state_changes(vec![StateChange::new(peer.clone())], gossiper);
+
+        assert_eq!(state.members.member_by_host_key(&peer.host_key()).unwrap().state(), MemberState::Alive);
+
+        let mut refuted = peer.clone();
+        refuted.set_state(MemberState::Alive);
+        refuted.set_incarnation(5);
+        state.apply_state_changes(vec![StateChange::new(refuted)], gossiper);
+
+        assert!(state.cleared_suspicions.is_empty());
+
+        let mut suspected_again = peer.clone();
+        suspected_again.set_incarnation(5);
+        state.apply_state_changes(vec![StateChange::new(suspected_again)], gossiper);
+
+        assert_eq!(state.members.member_by_host_key(&peer.host_key()).unwrap().state(), MemberState::Suspect);
+    }
+
+    #[test]
+    fn test_ping_waiters_match_the_acking_host_key() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let peer = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3001").unwrap(), 0, MemberState::Alive);
+        state.members.add_member(peer.clone());
+
+        let (tx, rx) = channel();
+        state.process_internal_request(InternalRequest::PingMember(
+            peer.host_key(), time::now_utc() + Duration::seconds(60), tx));
+
+        state.notify_ping_waiters(Uuid::new_v4());
+        assert!(rx.try_recv().is_err());
+
+        state.notify_ping_waiters(peer.host_key());
+        assert_eq!(rx.try_recv(), Ok(true));
+        assert!(state.ping_waiters.is_empty());
+
+        let (tx, rx) = channel();
+        state.process_internal_request(InternalRequest::PingMember(
+            peer.host_key(), time::now_utc() - Duration::seconds(1), tx));
+
+        state.prune_ping_waiters();
+        assert_eq!(rx.try_recv(), Ok(false));
+        assert!(state.ping_waiters.is_empty());
+    }
+
+    #[test]
+    fn test_departure_waiters_are_dropped_after_their_deadline() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let peer = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3001").unwrap(), 0, MemberState::Alive);
+        state.members.add_member(peer.clone());
+
+        let (expired_tx, expired_rx) = channel();
+        let (waiting_tx, _waiting_rx) = channel();
+        state.process_internal_request(InternalRequest::WaitForDeparture(
+            peer.host_key(), time::now_utc() - Duration::seconds(1), expired_tx));
+        state.process_internal_request(InternalRequest::WaitForDeparture(
+            peer.host_key(), time::now_utc() + Duration::seconds(60), waiting_tx));
+
+        state.prune_departure_waiters();
+
+        assert_eq!(expired_rx.try_recv(), Ok(false));
+        assert_eq!(state.departure_waiters.len(), 1);
+    }
+
+    #[test]
+    fn test_loaded_removals_stay_local_and_forget_the_address() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+        state.members.add_member(peer.clone());
+        state.pending_responses.push((time::now_utc(), addr, Vec::new(), Instant::now()));
+        state.ping_deadlines.push(addr);
+        state.rtt_estimates.insert(addr, Duration::milliseconds(5));
+
+        state.load_members(Vec::new());
+
+        assert!(state.members.member_by_host_key(&peer.host_key()).is_none());
+        assert!(state.pending_responses.is_empty());
+        assert!(state.ping_deadlines.is_empty());
+        assert!(state.rtt_estimates.is_empty());
+        assert!(state.state_changes.iter().all(|sc| sc.member().host_key() != peer.host_key()));
+    }
+
+    #[test]
+    fn test_only_the_previous_cluster_key_is_accepted_until_it_expires() {
+        let rotations = Arc::new(Mutex::new(0));
+        let provider_rotations = rotations.clone();
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            cluster_key_provider: Some(Box::new(move || {
+                let mut rotations = provider_rotations.lock().unwrap();
+                *rotations += 1;
+                format!("key-{}", *rotations).into_bytes()
+            })),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+
+        state.rotate_cluster_key();
+        assert!(state.accepts_cluster_key(&"key-1".as_bytes().to_vec()));
+        assert!(state.accepts_cluster_key(&"key-2".as_bytes().to_vec()));
+
+        state.rotate_cluster_key();
+        assert!(!state.accepts_cluster_key(&"key-1".as_bytes().to_vec()));
+        assert!(state.accepts_cluster_key(&"key-2".as_bytes().to_vec()));
+        assert!(state.config.accepted_cluster_keys.is_empty());
+
+        state.previous_cluster_key.as_mut().unwrap().1 = time::now_utc() - Duration::seconds(1);
+        assert!(!state.accepts_cluster_key(&"key-2".as_bytes().to_vec()));
+
+        state.expire_previous_cluster_key();
+        assert!(state.previous_cluster_key.is_none());
+        assert!(state.accepts_cluster_key(&"key-3".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_ping_observations_expire_and_fit_in_the_mtu() {
+        let mut observations = HashMap::new();
+        let window = Duration::seconds(10);
+        let now = time::now_utc();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        record_ping_observation(&mut observations, a, b, now - Duration::seconds(20), window);
+        record_ping_observation(&mut observations, a, c, now - Duration::seconds(1), window);
+        record_ping_observation(&mut observations, b, a, now, window);
+        record_ping_observation(&mut observations, c, c, now, window);
+
+        assert_eq!(recent_ping_observations(&observations), vec![(b, a), (a, c)]);
+
+        let mut message = build_message(&a, &Vec::new(), 0, Request::Ack, Vec::new(), 512).unwrap();
+        let base_len = json::encode(&message).unwrap().len();
+        attach_ping_observations(&mut message, recent_ping_observations(&observations), base_len + 100);
+
+        assert_eq!(message.ping_observations, Some(vec![(b, a)]));
+        assert!(json::encode(&message).unwrap().len() < base_len + 100);
+    }
+
+    #[test]
+    fn test_protocol_version_mismatches_are_reported_once_per_peer() {
+        let config = ClusterConfig {
+            listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+            .. ClusterConfig::default()
+        };
+        let (event_tx, _event_rx) = channel();
+        let (_event_loop, mut state) = State::new(Uuid::new_v4(), config, event_tx).unwrap();
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let mut message = build_message(&Uuid::new_v4(), &Vec::new(), 0, Request::Ack, Vec::new(), 512).unwrap();
+
+        state.check_protocol_version(addr, &message);
+        assert!(state.version_mismatches.is_empty());
+
+        message.protocol_version = None;
+        state.check_protocol_version(addr, &message);
+        state.check_protocol_version(addr, &message);
+        assert_eq!(state.version_mismatches.len(), 1);
+    }
+
+    #[test]
+    fn test_messages_from_older_peers_decode_without_the_newer_fields() {
+        let sender = Uuid::new_v4();
+        let encoded = format!("{{\"sender\":\"{}\",\"cluster_key\":[],\"request\":\"Ack\",\"state_changes\":[]}}",
+                              sender.to_hyphenated_string());
+
+        let message: Message = json::decode(&encoded).unwrap();
+
+        assert_eq!(message.protocol_version, None);
+        assert_eq!(message.sender, sender);
+        assert_eq!(message.sequence, None);
+        assert_eq!(message.timestamp_ms, None);
+        assert_eq!(message.ping_observations, None);
+        assert_eq!(message.monitor, None);
+    }
+
+    #[test]
+    fn test_monitor_mode_only_reports_up_and_down() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let member = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+
+        assert!(is_monitor_event(&MemberEvent::MemberJoined(member.clone())));
+        assert!(is_monitor_event(&MemberEvent::MemberWentUp(member.clone())));
+        assert!(is_monitor_event(&MemberEvent::MemberWentDown(member.clone())));
+        assert!(!is_monitor_event(&MemberEvent::MemberSuspectedDown(member.clone())));
+        assert!(!is_monitor_event(&MemberEvent::SuspicionCleared(member.clone())));
+        assert!(!is_monitor_event(&MemberEvent::MemberLeft(member)));
+    }
+
+    #[test]
+    fn test_oversized_metadata_is_rejected() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut member = Member::new(Uuid::new_v4(), addr, 3, MemberState::Alive);
+        member.set_tags((0..100).map(|i| format!("tag-{}", i)).collect());
+
+        let accepted = enforce_metadata_limit(StateChange::new(member.clone()), 4096);
+        assert!(accepted.member().structurally_eq(&member));
+
+        let rejected = enforce_metadata_limit(StateChange::new(member.clone()), 64);
+        assert!(rejected.member().tags().is_empty());
+        assert_eq!(rejected.member().host_key(), member.host_key());
+        assert_eq!(rejected.member().incarnation(), 3);
+    }
+
+    #[test]
+    fn test_member_events_are_sorted_by_priority() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let member = |state| Member::new(Uuid::new_v4(), addr, 0, state);
+
+        let mut events = vec![
+            MemberEvent::MemberJoined(member(MemberState::Alive)),
+            MemberEvent::MemberSuspectedDown(member(MemberState::Suspect)),
+            MemberEvent::MemberWentUp(member(MemberState::Alive)),
+            MemberEvent::MemberWentDown(member(MemberState::Down)),
+            MemberEvent::MemberLeft(member(MemberState::Left)),
+        ];
+
+        sort_member_events(&mut events, &default_event_priority);
+
+        let states: Vec<_> = events.iter().map(|e| e.member().state()).collect();
+        assert_eq!(states, vec![MemberState::Down, MemberState::Left, MemberState::Suspect,
+                                MemberState::Alive, MemberState::Alive]);
+
+        match events[3] {
+            MemberEvent::MemberJoined(_) => {},
+            ref e => panic!("Expected joins to keep their relative order, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_new_joiner_receives_current_self_metadata() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let state_changes = vec![StateChange::new(members.myself())];
+
+        let myself = members.set_tags(vec![String::from("gpu")].into_iter().collect());
+        let outgoing = current_self_state_changes(&state_changes, &myself, None);
+
+        let mut joiner = MemberList::new(Member::myself(Uuid::new_v4()));
+        let from: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let (new, _, _) = joiner.apply_state_changes(outgoing, &from, &DefaultConflictResolver, true, |_| true);
+
+        assert_eq!(new.len(), 1);
+        assert!(new[0].has_tag("gpu"));
+        assert_eq!(new[0].incarnation(), 1);
+        assert_eq!(new[0].remote_host(), Some(from));
+    }
 }
@@ -1,14 +1,21 @@
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::cmp::Ordering;
+use std::time::Instant;
 
 use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use rustc_serialize::hex::{FromHex, ToHex};
 use time;
 use time::Duration;
 use uuid::Uuid;
 
+const IDENTITY_NAMESPACE: [u8; 16] = [
+    0x5d, 0x3a, 0x8e, 0x41, 0x0c, 0x6f, 0x4b, 0x2e, 0x9a, 0x71, 0xd4, 0x13, 0xe8, 0x5c, 0x02, 0xb7,
+];
 
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Copy)]
 pub enum MemberState {
@@ -18,13 +25,23 @@ pub enum MemberState {
     Left,
 }
 
-#[derive(Clone, PartialEq, Eq)]
+/// Members compare, order and hash by `host_key` alone, so a member stays the
+/// same set or map key across address, state and incarnation changes. Use
+/// `structurally_eq` to compare every field.
+#[derive(Clone)]
 pub struct Member {
     host_key: Uuid,
     remote_host: Option<SocketAddr>,
     incarnation: u64,
     member_state: MemberState,
     last_state_change: time::Tm,
+    state_since: Instant,
+    tags: HashSet<String>,
+    config_epoch: u64,
+    cluster_size: u64,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+    clock_offset: Option<Duration>,
 }
 
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
@@ -37,6 +54,9 @@ impl Member {
         Member {
             host_key: host_key, remote_host: Some(remote_host), incarnation: incarnation,
             member_state: known_state, last_state_change: time::now_utc(),
+            state_since: Instant::now(), tags: HashSet::new(),
+            config_epoch: 0, cluster_size: 0, public_key: Vec::new(), signature: Vec::new(),
+            clock_offset: None,
         }
     }
 
@@ -44,9 +64,21 @@ impl Member {
         Member {
             host_key: host_key, remote_host: None, incarnation: 0,
             member_state: MemberState::Alive, last_state_change: time::now_utc(),
+            state_since: Instant::now(), tags: HashSet::new(),
+            config_epoch: 0, cluster_size: 0, public_key: Vec::new(), signature: Vec::new(),
+            clock_offset: None,
         }
     }
 
+    /// Derives a stable version 5 host key from `seed`, such as a hostname
+    /// and port. Passing the result to `start_cluster` lets a node keep its
+    /// identity across restarts without storing a UUID.
+    pub fn deterministic_id(seed: &str) -> Uuid {
+        let namespace = Uuid::from_bytes(&IDENTITY_NAMESPACE).unwrap();
+
+        Uuid::new_v5(&namespace, seed)
+    }
+
     pub fn host_key(&self) -> Uuid {
         self.host_key.clone()
     }
@@ -67,6 +99,21 @@ impl Member {
         self.last_state_change + duration < time::now_utc()
     }
 
+    /// How long this node has seen the member in its current state. Unlike
+    /// `last_state_change`, this is measured on the local monotonic clock and
+    /// is never taken from gossip.
+    pub fn age(&self) -> Duration {
+        Duration::from_std(self.state_since.elapsed()).unwrap_or(Duration::max_value())
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub fn last_state_change(&self) -> time::Tm {
+        self.last_state_change
+    }
+
     pub fn state(&self) -> MemberState {
         self.member_state
     }
@@ -75,9 +122,101 @@ impl Member {
         if self.member_state != state {
             self.member_state = state;
             self.last_state_change = time::now_utc();
+            self.state_since = Instant::now();
         }
     }
 
+    /// Keeps the local age of `other` when replacing it with newer data for
+    /// the same state, such as a higher incarnation.
+    pub fn carry_state_since(&mut self, other: &Member) {
+        self.state_since = other.state_since;
+    }
+
+    pub fn tags(&self) -> &HashSet<String> {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    pub fn set_tags(&mut self, tags: HashSet<String>) {
+        self.tags = tags;
+    }
+
+    pub fn metadata_size(&self) -> usize {
+        self.tags.iter().map(|t| t.len()).sum()
+    }
+
+    pub fn config_epoch(&self) -> u64 {
+        self.config_epoch
+    }
+
+    pub fn set_config_epoch(&mut self, config_epoch: u64) {
+        self.config_epoch = config_epoch;
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.cluster_size
+    }
+
+    pub fn set_cluster_size(&mut self, cluster_size: u64) {
+        self.cluster_size = cluster_size;
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    pub fn set_identity(&mut self, public_key: Vec<u8>, signature: Vec<u8>) {
+        self.public_key = public_key;
+        self.signature = signature;
+    }
+
+    /// Bytes covered by the member's signature. Suspect and Down are reported
+    /// by other members, so they are covered by the member's own Alive claim.
+    pub fn identity_payload(&self) -> Vec<u8> {
+        let mut payload = self.host_key.as_bytes().to_vec();
+
+        for shift in (0..8).rev() {
+            payload.push((self.incarnation >> (shift * 8)) as u8);
+        }
+
+        payload.push(if self.member_state == MemberState::Left { 1 } else { 0 });
+
+        let host = self.remote_host.map(|host| host.to_string()).unwrap_or_default();
+        payload.push(host.len() as u8);
+        payload.extend_from_slice(host.as_bytes());
+
+        payload.extend_from_slice(&self.public_key);
+        payload
+    }
+
+    pub fn clock_offset(&self) -> Option<Duration> {
+        self.clock_offset
+    }
+
+    pub fn set_clock_offset(&mut self, clock_offset: Option<Duration>) {
+        self.clock_offset = clock_offset;
+    }
+
+    pub fn structurally_eq(&self, other: &Member) -> bool {
+        self.host_key == other.host_key
+            && self.remote_host == other.remote_host
+            && self.incarnation == other.incarnation
+            && self.member_state == other.member_state
+            && self.last_state_change == other.last_state_change
+            && self.tags == other.tags
+            && self.config_epoch == other.config_epoch
+            && self.cluster_size == other.cluster_size
+            && self.public_key == other.public_key
+            && self.signature == other.signature
+    }
+
     pub fn member_by_changing_host(&self, remote_host: SocketAddr) -> Member {
         Member {
             remote_host: Some(remote_host),
@@ -85,8 +224,15 @@ impl Member {
         }
     }
 
+    pub fn set_incarnation(&mut self, incarnation: u64) {
+        self.incarnation = incarnation;
+    }
+
     pub fn reincarnate(&mut self) {
-        self.incarnation += 1
+        match self.incarnation.checked_add(1) {
+            Some(incarnation) => self.incarnation = incarnation,
+            None => println!("WARNING: Incarnation of {:?} has reached its maximum, not incrementing", self),
+        }
     }
 }
 
@@ -106,7 +252,7 @@ impl StateChange {
 
 impl Decodable for Member {
     fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("m", 5, |d| {
+        d.read_struct("m", 10, |d| {
             let host_key = try!(d.read_struct_field("h", 0, |d| Decodable::decode(d)));
             let remote_host = try!(d.read_struct_field("r", 1, |d| {
                 d.read_option(|d, b| {
@@ -124,6 +270,11 @@ impl Decodable for Member {
             let incarnation = try!(d.read_struct_field("i", 2, |d| Decodable::decode(d)));
             let member_state = try!(d.read_struct_field("m", 3, |d| Decodable::decode(d)));
             let (sec, nsec) = try!(d.read_struct_field("t", 4, |d| Decodable::decode(d)));
+            let tags: Option<HashSet<String>> = try!(d.read_struct_field("g", 5, |d| Decodable::decode(d)));
+            let config_epoch: Option<u64> = try!(d.read_struct_field("e", 6, |d| Decodable::decode(d)));
+            let cluster_size: Option<u64> = try!(d.read_struct_field("n", 7, |d| Decodable::decode(d)));
+            let public_key = try!(d.read_struct_field("k", 8, |d| decode_optional_hex(d)));
+            let signature = try!(d.read_struct_field("s", 9, |d| decode_optional_hex(d)));
 
             Ok(Member {
                 host_key: host_key,
@@ -131,14 +282,32 @@ impl Decodable for Member {
                 incarnation: incarnation,
                 member_state: member_state,
                 last_state_change: time::at_utc(time::Timespec::new(sec, nsec)),
+                state_since: Instant::now(),
+                tags: tags.unwrap_or_else(HashSet::new),
+                config_epoch: config_epoch.unwrap_or(0),
+                cluster_size: cluster_size.unwrap_or(0),
+                public_key: public_key,
+                signature: signature,
+                clock_offset: None,
             })
         })
     }
 }
 
+fn decode_hex<D: Decoder>(d: &mut D) -> Result<Vec<u8>, D::Error> {
+    let encoded = try!(d.read_str());
+
+    encoded.from_hex().map_err(|_| d.error("invalid hex string"))
+}
+
+/// Older peers do not send identity fields at all, which reads as empty.
+fn decode_optional_hex<D: Decoder>(d: &mut D) -> Result<Vec<u8>, D::Error> {
+    d.read_option(|d, present| if present { decode_hex(d) } else { Ok(Vec::new()) })
+}
+
 impl Encodable for Member {
     fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
-        e.emit_struct("m", 5, |e| {
+        e.emit_struct("m", 10, |e| {
             try!(e.emit_struct_field("h", 0, |e| self.host_key.encode(e)));
             try!(e.emit_struct_field("r", 1, |e| {
                 e.emit_option(|e| {
@@ -150,31 +319,36 @@ impl Encodable for Member {
             }));
             try!(e.emit_struct_field("i", 2, |e| self.incarnation.encode(e)));
             try!(e.emit_struct_field("m", 3, |e| self.member_state.encode(e)));
-            e.emit_struct_field("t", 4, |e| {
+            try!(e.emit_struct_field("t", 4, |e| {
                 let spec = self.last_state_change.to_timespec();
                 (spec.sec, spec.nsec).encode(e)
-            })
+            }));
+            try!(e.emit_struct_field("g", 5, |e| self.tags.encode(e)));
+            try!(e.emit_struct_field("e", 6, |e| self.config_epoch.encode(e)));
+            try!(e.emit_struct_field("n", 7, |e| self.cluster_size.encode(e)));
+            try!(e.emit_struct_field("k", 8, |e| self.public_key.to_hex().encode(e)));
+            e.emit_struct_field("s", 9, |e| self.signature.to_hex().encode(e))
         })
     }
 }
 
-impl PartialOrd for Member {
-    fn partial_cmp(&self, rhs: &Member) -> Option<Ordering> {
-        let t1 = (
-            self.host_key.as_bytes(),
-            format!("{:?}", self.remote_host),
-            self.incarnation,
-            self.member_state.clone(),
-        );
+impl PartialEq for Member {
+    fn eq(&self, rhs: &Member) -> bool {
+        self.host_key == rhs.host_key
+    }
+}
 
-        let t2 = (
-            rhs.host_key.as_bytes(),
-            format!("{:?}", rhs.remote_host),
-            rhs.incarnation,
-            rhs.member_state.clone(),
-        );
+impl Eq for Member {}
 
-        return t1.partial_cmp(&t2);
+impl Hash for Member {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.host_key.hash(state);
+    }
+}
+
+impl PartialOrd for Member {
+    fn partial_cmp(&self, rhs: &Member) -> Option<Ordering> {
+        self.host_key.as_bytes().partial_cmp(rhs.host_key.as_bytes())
     }
 }
 
@@ -199,6 +373,28 @@ impl Debug for Member {
     }
 }
 
+/// How a member that was declared `Left` is treated when it shows up alive
+/// again with the same host key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeftRejoinPolicy {
+    /// Keep the member `Left` and ignore its rejoin
+    Reject,
+    /// Admit the member again as if it had just joined
+    Readmit,
+}
+
+pub trait ConflictResolver: Send {
+    fn resolve<'a>(&self, lhs: &'a Member, rhs: &'a Member) -> &'a Member;
+}
+
+pub struct DefaultConflictResolver;
+
+impl ConflictResolver for DefaultConflictResolver {
+    fn resolve<'a>(&self, lhs: &'a Member, rhs: &'a Member) -> &'a Member {
+        most_recent_member_data(lhs, rhs)
+    }
+}
+
 pub fn most_recent_member_data<'a>(lhs: &'a Member, rhs: &'a Member) -> &'a Member {
     use member::MemberState::*;
 
@@ -218,12 +414,45 @@ pub fn most_recent_member_data<'a>(lhs: &'a Member, rhs: &'a Member) -> &'a Memb
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashSet;
     use std::str::FromStr;
+    use std::time::{Duration as StdDuration, Instant};
 
     use rustc_serialize::json;
     use uuid;
     use time;
-    use super::{Member, MemberState};
+    use std::u64;
+
+    use super::{Member, MemberState, ConflictResolver, DefaultConflictResolver};
+
+    #[test]
+    fn test_age_tracks_local_transitions_only() {
+        let mut member = Member::new(uuid::Uuid::new_v4(), FromStr::from_str("127.0.0.1:2552").unwrap(), 0,
+                                     MemberState::Suspect);
+        member.state_since = Instant::now() - StdDuration::from_secs(8);
+        member.last_state_change = time::at_utc(time::Timespec::new(0, 0));
+
+        assert!(member.age() >= time::Duration::seconds(8));
+        assert!(member.age() < time::Duration::seconds(60));
+
+        let mut refreshed = member.clone();
+        refreshed.set_incarnation(1);
+        refreshed.carry_state_since(&member);
+        assert!(refreshed.age() >= time::Duration::seconds(8));
+
+        refreshed.set_state(MemberState::Down);
+        assert!(refreshed.age() < time::Duration::seconds(8));
+    }
+
+    #[test]
+    fn test_deterministic_id_is_stable_per_seed() {
+        let id = Member::deterministic_id("node-1.example.com:2552");
+
+        assert_eq!(id, Member::deterministic_id("node-1.example.com:2552"));
+        assert!(id != Member::deterministic_id("node-2.example.com:2552"));
+        assert_eq!(id.as_bytes()[6] >> 4, 5);
+        assert_eq!(id.as_bytes()[8] >> 6, 2);
+    }
 
     #[test]
     fn test_member_encode_decode() {
@@ -233,11 +462,73 @@ mod test {
             incarnation: 123,
             member_state: MemberState::Alive,
             last_state_change: time::at_utc(time::Timespec::new(123, 456)),
+            state_since: Instant::now(),
+            tags: vec![String::from("gpu")].into_iter().collect(),
+            config_epoch: 7,
+            cluster_size: 12,
+            public_key: vec![1, 2, 3],
+            signature: vec![4, 5, 6],
+            clock_offset: None,
         };
 
         let encoded = json::encode(&member).unwrap();
         let decoded : Member = json::decode(&encoded).unwrap();
 
-        assert_eq!(decoded, member);
+        assert!(decoded.structurally_eq(&member));
+    }
+
+    #[test]
+    fn test_members_from_older_peers_decode_with_defaults() {
+        let host_key = uuid::Uuid::new_v4();
+        let encoded = format!("{{\"h\":\"{}\",\"r\":\"127.0.0.1:2552\",\"i\":3,\"m\":\"Alive\",\"t\":[123,456]}}",
+                              host_key.to_hyphenated_string());
+
+        let decoded: Member = json::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.host_key(), host_key);
+        assert_eq!(decoded.incarnation(), 3);
+        assert!(decoded.tags().is_empty());
+        assert_eq!(decoded.config_epoch(), 0);
+        assert_eq!(decoded.cluster_size(), 0);
+        assert!(decoded.public_key().is_empty());
+        assert!(decoded.signature().is_empty());
+    }
+
+    #[test]
+    fn test_members_are_identified_by_host_key() {
+        let host_key = uuid::Uuid::new_v4();
+        let alive = Member::new(host_key, FromStr::from_str("127.0.0.1:2552").unwrap(), 1, MemberState::Alive);
+        let moved = Member::new(host_key, FromStr::from_str("127.0.0.1:2553").unwrap(), 2, MemberState::Suspect);
+
+        assert_eq!(alive, moved);
+        assert!(!alive.structurally_eq(&moved));
+
+        let members: HashSet<Member> = vec![alive, moved].into_iter().collect();
+        assert_eq!(members.len(), 1);
+    }
+
+    #[test]
+    fn test_default_resolver_prefers_higher_incarnation() {
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let host_key = uuid::Uuid::new_v4();
+        let old = Member::new(host_key, addr, 1, MemberState::Alive);
+        let new = Member::new(host_key, addr, 2, MemberState::Suspect);
+
+        assert_eq!(DefaultConflictResolver.resolve(&old, &new).incarnation(), 2);
+        assert_eq!(DefaultConflictResolver.resolve(&new, &old).incarnation(), 2);
+    }
+
+    #[test]
+    fn test_reincarnate_saturates_at_maximum() {
+        let addr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let host_key = uuid::Uuid::new_v4();
+        let mut member = Member::new(host_key, addr, u64::MAX, MemberState::Alive);
+        let stale = Member::new(host_key, addr, 0, MemberState::Alive);
+
+        member.reincarnate();
+
+        assert_eq!(member.incarnation(), u64::MAX);
+        assert_eq!(DefaultConflictResolver.resolve(&member, &stale).incarnation(), u64::MAX);
+        assert_eq!(DefaultConflictResolver.resolve(&stale, &member).incarnation(), u64::MAX);
     }
 }
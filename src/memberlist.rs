@@ -1,25 +1,51 @@
+use std::cmp;
+use std::i64;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::time::Instant;
 
 use rand;
 use rand::Rng;
+use time;
 use time::Duration;
 use uuid::Uuid;
 
-use member;
-use member::{Member, MemberState, StateChange};
+use member::{ConflictResolver, LeftRejoinPolicy, Member, MemberState, StateChange};
 
 pub struct MemberList {
     members: Vec<Member>,
     periodic_index: usize,
+    last_probed: HashMap<Uuid, time::Tm>,
+    weighted_selection: bool,
+    relay_index: usize,
+    join_times: HashMap<Uuid, time::Tm>,
+    last_acked: HashMap<Uuid, time::Tm>,
+    history: HashMap<Uuid, VecDeque<(MemberState, Instant)>>,
+    history_limit: usize,
+    left_rejoin_policy: LeftRejoinPolicy,
 }
 
 impl MemberList {
     pub fn new(me: Member) -> Self {
+        MemberList::with_capacity(me, 0)
+    }
+
+    pub fn with_capacity(me: Member, capacity: usize) -> Self {
+        let mut members = Vec::with_capacity(capacity);
+        members.push(me);
+
         MemberList {
-            members: vec![me],
+            members: members,
             periodic_index: 0,
+            last_probed: HashMap::with_capacity(capacity),
+            weighted_selection: false,
+            relay_index: 0,
+            join_times: HashMap::with_capacity(capacity),
+            last_acked: HashMap::with_capacity(capacity),
+            history: HashMap::new(),
+            history_limit: 0,
+            left_rejoin_policy: LeftRejoinPolicy::Reject,
         }
     }
 
@@ -27,8 +53,128 @@ impl MemberList {
         self.members.iter().filter(|ref m| m.state() != MemberState::Left).cloned().collect()
     }
 
+    /// Like `available_nodes`, but fills a caller-owned buffer so its
+    /// allocation can be reused between snapshots.
+    pub fn available_nodes_into(&self, buffer: &mut Vec<Member>) {
+        buffer.clear();
+        buffer.extend(self.members.iter().filter(|m| m.state() != MemberState::Left).cloned());
+    }
+
+    pub fn for_each_available<F: FnMut(&Member)>(&self, mut f: F) {
+        for member in self.members.iter().filter(|m| m.state() != MemberState::Left) {
+            f(member);
+        }
+    }
+
+    pub fn available_nodes_page(&self, offset: usize, limit: usize) -> (Vec<Member>, bool) {
+        let mut available: Vec<_> = self.members.iter().filter(|m| m.state() != MemberState::Left).collect();
+        available.sort_by(|a, b| a.host_key().as_bytes().cmp(b.host_key().as_bytes()));
+
+        let page = available.iter().skip(offset).take(limit).map(|&m| m.clone()).collect();
+        let has_more = offset.saturating_add(limit) < available.len();
+
+        (page, has_more)
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+
+        for transitions in self.history.values_mut() {
+            trim_transitions(transitions, limit);
+        }
+
+        self.history.retain(|_, transitions| !transitions.is_empty());
+    }
+
+    /// The most recent state transitions observed locally for `host_key`,
+    /// oldest first. Only kept when a history limit has been set.
+    pub fn recent_transitions(&self, host_key: &Uuid) -> Vec<(MemberState, Instant)> {
+        self.history.get(host_key).map_or(Vec::new(), |transitions| transitions.iter().cloned().collect())
+    }
+
+    pub fn set_left_rejoin_policy(&mut self, policy: LeftRejoinPolicy) {
+        self.left_rejoin_policy = policy;
+    }
+
+    /// Picks ping targets at random, weighted towards the least recently probed
+    /// members, instead of the default shuffled round-robin.
+    pub fn set_weighted_selection(&mut self, weighted: bool) {
+        self.weighted_selection = weighted;
+    }
+
     pub fn to_map(&self) -> HashMap<Uuid, Member> {
-        self.members.iter().map(|ref m| (m.host_key().clone(), (*m).clone())).collect()
+        let mut map = HashMap::with_capacity(self.members.capacity());
+        map.extend(self.members.iter().map(|m| (m.host_key(), m.clone())));
+
+        map
+    }
+
+    /// Removes remote members that have been Down or Left for at least
+    /// `older_than`, along with their bookkeeping. A reaped member is unknown
+    /// again, so a stale rumour about it can bring it back, and a departed
+    /// member is no longer held to the left rejoin policy.
+    pub fn reap(&mut self, older_than: Duration) -> Vec<Member> {
+        let now = time::now_utc();
+        let mut reaped = Vec::new();
+        let mut index = 0;
+
+        while index < self.members.len() {
+            let reapable = {
+                let member = &self.members[index];
+
+                member.is_remote()
+                    && (member.state() == MemberState::Down || member.state() == MemberState::Left)
+                    && member.last_state_change() + older_than <= now
+            };
+
+            if reapable {
+                reaped.push(self.members.swap_remove(index));
+            }
+            else {
+                index += 1;
+            }
+        }
+
+        for member in &reaped {
+            self.last_probed.remove(&member.host_key());
+            self.join_times.remove(&member.host_key());
+            self.last_acked.remove(&member.host_key());
+            self.history.remove(&member.host_key());
+        }
+
+        reaped
+    }
+
+    /// Releases excess capacity once fewer than `min_load_factor` of the
+    /// allocated member slots are in use, keeping room for at least
+    /// `min_capacity` members. Returns whether anything was compacted.
+    pub fn compact(&mut self, min_load_factor: f64, min_capacity: usize) -> bool {
+        let capacity = cmp::max(self.members.capacity(), 1);
+
+        if capacity <= min_capacity || self.members.len() as f64 / capacity as f64 >= min_load_factor {
+            return false;
+        }
+
+        let present: HashSet<Uuid> = self.members.iter().map(|m| m.host_key()).collect();
+
+        compact_times(&mut self.last_probed, &present, min_capacity);
+        compact_times(&mut self.join_times, &present, min_capacity);
+        compact_times(&mut self.last_acked, &present, min_capacity);
+        self.history.retain(|host_key, _| present.contains(host_key));
+        self.members.shrink_to_fit();
+
+        let len = self.members.len();
+        self.members.reserve_exact(min_capacity.saturating_sub(len));
+
+        true
+    }
+
+    /// Forgets join times once their grace period has passed, so only members
+    /// still protected from early suspicion are tracked.
+    pub fn expire_join_times(&mut self, new_member_grace: Duration) {
+        let now = time::now_utc();
+
+        self.join_times.retain(|_, joined| *joined + new_member_grace > now);
     }
 
     fn mut_myself(&mut self) -> &mut Member {
@@ -41,6 +187,29 @@ impl MemberList {
         panic!("Could not find myself as member");
     }
 
+    pub fn myself(&self) -> Member {
+        let mut myself = self.members.iter().find(|m| m.is_myself()).cloned().expect("Could not find myself as member");
+        myself.set_cluster_size(self.local_size() as u64);
+
+        myself
+    }
+
+    fn local_size(&self) -> usize {
+        self.members.iter().filter(|m| m.state() == MemberState::Alive || m.state() == MemberState::Suspect).count()
+    }
+
+    pub fn estimated_size(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|m| m.is_remote() && m.state() == MemberState::Alive)
+            .map(|m| m.cluster_size() as usize)
+            .fold(self.local_size(), cmp::max)
+    }
+
+    pub fn has_alive_remote(&self) -> bool {
+        self.members.iter().any(|m| m.is_remote() && m.state() == MemberState::Alive)
+    }
+
     pub fn reincarnate_self(&mut self) -> Member {
         let myself = self.mut_myself();
         myself.reincarnate();
@@ -48,15 +217,79 @@ impl MemberList {
         myself.clone()
     }
 
-    pub fn leave(&mut self) -> Member {
+    /// Moves our incarnation past `incarnation`, so that our own claims win
+    /// over whatever the rest of the cluster remembers about us.
+    pub fn reincarnate_self_above(&mut self, incarnation: u64) -> Member {
         let myself = self.mut_myself();
-        myself.set_state(MemberState::Left);
+
+        if myself.incarnation() < incarnation {
+            myself.set_incarnation(incarnation);
+        }
+
         myself.reincarnate();
 
         myself.clone()
     }
 
+    pub fn set_tags(&mut self, tags: HashSet<String>) -> Member {
+        let myself = self.mut_myself();
+        myself.set_tags(tags);
+        myself.reincarnate();
+
+        myself.clone()
+    }
+
+    pub fn set_config_epoch(&mut self, config_epoch: u64) -> Member {
+        let myself = self.mut_myself();
+        myself.set_config_epoch(config_epoch);
+        myself.reincarnate();
+
+        myself.clone()
+    }
+
+    pub fn min_config_epoch(&self) -> u64 {
+        self.members
+            .iter()
+            .filter(|m| m.state() == MemberState::Alive)
+            .map(|m| m.config_epoch())
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn members_with_tag(&self, tag: &str) -> Vec<Member> {
+        self.members.iter().filter(|m| m.state() != MemberState::Left && m.has_tag(tag)).cloned().collect()
+    }
+
+    pub fn leave(&mut self) -> Member {
+        let myself = {
+            let myself = self.mut_myself();
+            myself.set_state(MemberState::Left);
+            myself.reincarnate();
+
+            myself.clone()
+        };
+
+        record_transition(&mut self.history, self.history_limit, &myself);
+
+        myself
+    }
+
     pub fn next_random_member(&mut self) -> Option<Member> {
+        let member = if self.weighted_selection {
+            self.next_weighted_member()
+        }
+        else {
+            self.next_round_robin_member()
+        };
+
+        if let Some(ref member) = member {
+            self.last_probed.insert(member.host_key(), time::now_utc());
+        }
+
+        member
+    }
+
+    fn next_round_robin_member(&mut self) -> Option<Member> {
         if self.periodic_index == 0 {
             rand::thread_rng().shuffle(&mut self.members);
         }
@@ -72,76 +305,217 @@ impl MemberList {
         }
     }
 
-    pub fn time_out_nodes(&mut self, expired_hosts: HashSet<SocketAddr>) -> (Vec<Member>, Vec<Member>) {
+    fn next_weighted_member(&self) -> Option<Member> {
+        let now = time::now_utc();
+        let other_members: Vec<_> = self.members.iter().filter(|&m| m.is_remote()).collect();
+
+        if other_members.len() == 0 {
+            return None;
+        }
+
+        let weights: Vec<u64> = other_members
+            .iter()
+            .map(|m| {
+                let last_probed = self.last_probed.get(&m.host_key())
+                    .or(self.join_times.get(&m.host_key()))
+                    .cloned()
+                    .unwrap_or(time::at_utc(time::Timespec::new(0, 0)));
+
+                cmp::max((now - last_probed).num_milliseconds(), 0) as u64 + 1
+            })
+            .collect();
+
+        let total = weights.iter().fold(0u64, |acc, &w| acc.saturating_add(w));
+        let index = pick_weighted(&weights, rand::thread_rng().gen_range(0, total));
+
+        Some(other_members[index].clone())
+    }
+
+    pub fn time_out_nodes<F>(&mut self,
+                             expired_hosts: Vec<SocketAddr>,
+                             suspicion_timeout: Duration,
+                             new_member_grace: Duration,
+                             max_suspicions: usize,
+                             escalated: &HashSet<SocketAddr>,
+                             is_confirmed: F) -> (Vec<Member>, Vec<Member>, Vec<SocketAddr>)
+        where F: Fn(&Member) -> bool
+    {
         let mut suspect_members = Vec::new();
         let mut down_members = Vec::new();
+        let mut deferred_hosts = Vec::new();
+        let mut newly_suspected = 0;
 
-        for mut member in self.members.iter_mut() {
-            if let Some(remote_host) = member.remote_host() {
-                if !expired_hosts.contains(&remote_host) {
+        for remote_host in expired_hosts {
+            for member in self.members.iter_mut().filter(|m| m.remote_host() == Some(remote_host)) {
+                let in_grace_period = self.join_times
+                    .get(&member.host_key())
+                    .map_or(false, |&joined| joined + new_member_grace > time::now_utc());
+
+                if member.state() == MemberState::Alive && in_grace_period {
                     continue;
                 }
 
-                if member.state() == MemberState::Alive {
+                // Hosts escalated by the fast path already used up their share of the cap
+                let already_escalated = escalated.contains(&remote_host);
+
+                if member.state() == MemberState::Alive && !already_escalated && newly_suspected >= max_suspicions {
+                    deferred_hosts.push(remote_host);
+                }
+                else if member.state() == MemberState::Alive {
                     member.set_state(MemberState::Suspect);
+                    record_transition(&mut self.history, self.history_limit, member);
                     suspect_members.push(member.clone());
+
+                    if !already_escalated {
+                        newly_suspected += 1;
+                    }
                 }
-                else if member.state() == MemberState::Suspect && member.state_change_older_than(Duration::seconds(3)) {
+                else if member.state() == MemberState::Suspect
+                    && member.state_change_older_than(suspicion_timeout)
+                    && is_confirmed(member) {
                     member.set_state(MemberState::Down);
+                    record_transition(&mut self.history, self.history_limit, member);
                     down_members.push(member.clone());
                 }
             }
         }
 
-        (suspect_members, down_members)
+        (suspect_members, down_members, deferred_hosts)
     }
 
-    pub fn mark_node_alive(&mut self, src_addr: &SocketAddr) -> Option<Member> {
+    pub fn mark_node_alive(&mut self, src_addr: &SocketAddr) -> Option<(MemberState, Member)> {
         for mut member in self.members.iter_mut() {
             if member.remote_host() == Some(*src_addr) && member.state() != MemberState::Alive {
+                let previous_state = member.state();
                 member.set_state(MemberState::Alive);
+                record_transition(&mut self.history, self.history_limit, member);
 
-                return Some(member.clone())
+                return Some((previous_state, member.clone()))
             }
         }
 
         None
     }
 
-    pub fn apply_state_changes(&mut self, state_changes: Vec<StateChange>, from: &SocketAddr) -> (Vec<Member>, Vec<Member>) {
+    pub fn set_clock_offset(&mut self, remote_host: &SocketAddr, clock_offset: Duration) {
+        for member in self.members.iter_mut().filter(|m| m.remote_host() == Some(*remote_host)) {
+            member.set_clock_offset(Some(clock_offset));
+        }
+    }
+
+    pub fn clear_suspicion(&mut self, host_key: &Uuid) -> Option<Member> {
+        for member in self.members.iter_mut() {
+            if member.host_key() == *host_key && member.is_remote() && member.state() == MemberState::Suspect {
+                member.set_state(MemberState::Alive);
+                record_transition(&mut self.history, self.history_limit, member);
+
+                return Some(member.clone());
+            }
+        }
+
+        None
+    }
+
+    pub fn apply_state_changes<F>(&mut self,
+                                  state_changes: Vec<StateChange>,
+                                  from: &SocketAddr,
+                                  resolver: &ConflictResolver,
+                                  assume_new_members_alive: bool,
+                                  may_refute: F) -> (Vec<Member>, Vec<(MemberState, Member)>, Vec<Member>)
+        where F: Fn(MemberState) -> bool
+    {
         let mut current_members = self.to_map();
 
         let mut changed_nodes = Vec::new();
+        let mut updated_nodes = Vec::new();
         let mut new_nodes = Vec::new();
 
         let my_host_key = self.mut_myself().host_key();
+        let history_limit = self.history_limit;
+        let left_rejoin_policy = self.left_rejoin_policy;
 
         for state_change in state_changes {
             let new_member_data = state_change.member();
+            let known = current_members.get(&new_member_data.host_key()).map(|m| (m.state(), m.incarnation()));
+
+            // Under Readmit, only an Alive claim newer than the departure brings a member back, and
+            // departures older than the readmitted incarnation are stale rumours
+            let rejoining = match known {
+                Some((MemberState::Left, _)) => new_member_data.state() != MemberState::Left,
+                _ => false,
+            };
+            let readmit = rejoining && left_rejoin_policy == LeftRejoinPolicy::Readmit
+                && new_member_data.state() == MemberState::Alive
+                && known.map_or(false, |(_, incarnation)| new_member_data.incarnation() > incarnation);
+            let stale_departure = left_rejoin_policy == LeftRejoinPolicy::Readmit
+                && new_member_data.state() == MemberState::Left
+                && known.map_or(false, |(state, incarnation)| {
+                    state != MemberState::Left && new_member_data.incarnation() < incarnation
+                });
+
+            if (rejoining && !readmit) || stale_departure {
+                continue;
+            }
+
             let old_member_data = current_members.entry(new_member_data.host_key());
 
             if new_member_data.host_key() == my_host_key {
-                if new_member_data.state() != MemberState::Alive {
-                    let myself = self.reincarnate_self();
-                    changed_nodes.push(myself.clone());
+                if new_member_data.state() != MemberState::Alive && may_refute(new_member_data.state()) {
+                    let myself = self.reincarnate_self_above(new_member_data.incarnation());
+                    changed_nodes.push((myself.state(), myself.clone()));
+                }
+                else if new_member_data.state() == MemberState::Alive
+                    && new_member_data.incarnation() > self.myself().incarnation() {
+                    updated_nodes.push(self.reincarnate_self_above(new_member_data.incarnation()));
                 }
             }
             else {
                 match old_member_data {
                     Entry::Occupied(mut entry) => {
-                        let new_member = member::most_recent_member_data(&new_member_data, entry.get()).clone();
+                        if readmit {
+                            let new_host = new_member_data.remote_host().unwrap_or(*from);
+                            let mut new_member = new_member_data.member_by_changing_host(new_host);
+
+                            if !assume_new_members_alive && new_member.state() == MemberState::Alive {
+                                new_member.set_state(MemberState::Suspect);
+                            }
+
+                            record_transition(&mut self.history, history_limit, &new_member);
+                            self.join_times.insert(new_member.host_key(), time::now_utc());
+                            entry.insert(new_member.clone());
+                            new_nodes.push(new_member);
+                            continue;
+                        }
+
+                        let new_member = resolver.resolve(&new_member_data, entry.get()).clone();
                         let new_host = new_member.remote_host().or(entry.get().remote_host()).unwrap();
-                        let new_member = new_member.member_by_changing_host(new_host);
+                        let mut new_member = new_member.member_by_changing_host(new_host);
+                        new_member.set_clock_offset(entry.get().clock_offset());
 
                         if new_member.state() != entry.get().state() {
+                            record_transition(&mut self.history, history_limit, &new_member);
+                            let previous = entry.insert(new_member.clone());
+                            changed_nodes.push((previous.state(), new_member));
+                        }
+                        else if new_member.incarnation() > entry.get().incarnation() {
+                            new_member.carry_state_since(entry.get());
                             entry.insert(new_member.clone());
-                            changed_nodes.push(new_member);
+                            updated_nodes.push(new_member);
+                        }
+                        else if new_member_data.incarnation() == entry.get().incarnation() {
+                            entry.get_mut().set_cluster_size(new_member_data.cluster_size());
                         }
                     },
                     Entry::Vacant(entry) => {
                         let new_host = new_member_data.remote_host().unwrap_or(*from);
-                        let new_member = new_member_data.member_by_changing_host(new_host);
+                        let mut new_member = new_member_data.member_by_changing_host(new_host);
+
+                        if !assume_new_members_alive && new_member.state() == MemberState::Alive {
+                            new_member.set_state(MemberState::Suspect);
+                            record_transition(&mut self.history, history_limit, &new_member);
+                        }
 
+                        self.join_times.insert(new_member.host_key(), time::now_utc());
                         entry.insert(new_member.clone());
                         new_nodes.push(new_member);
                     }
@@ -149,32 +523,690 @@ impl MemberList {
             }
         }
 
-        self.members = current_members.values().cloned().collect();
+        self.members.clear();
+        self.members.extend(current_members.into_iter().map(|(_, m)| m));
+
+        (new_nodes, changed_nodes, updated_nodes)
+    }
+
+    /// Replaces every remote member with `loaded`. Members that are already
+    /// known keep their current data unless the loaded copy has a higher
+    /// incarnation. Returns the added, state-changed, updated and removed
+    /// members.
+    pub fn load_members(&mut self, loaded: Vec<Member>)
+                        -> (Vec<Member>, Vec<(MemberState, Member)>, Vec<Member>, Vec<Member>) {
+        let mut current = self.to_map();
+        let myself = self.myself();
+        let history_limit = self.history_limit;
+
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        let mut updated = Vec::new();
+        let mut kept = HashSet::new();
+        kept.insert(myself.host_key());
+
+        for mut member in loaded.into_iter().filter(|m| m.is_remote() && m.host_key() != myself.host_key()) {
+            kept.insert(member.host_key());
+
+            match current.entry(member.host_key()) {
+                Entry::Occupied(mut entry) => {
+                    if member.incarnation() > entry.get().incarnation() {
+                        member.set_clock_offset(entry.get().clock_offset());
+
+                        let previous_state = entry.get().state();
+                        if member.state() != previous_state {
+                            record_transition(&mut self.history, history_limit, &member);
+                            changed.push((previous_state, member.clone()));
+                        }
+                        else {
+                            member.carry_state_since(entry.get());
+                            updated.push(member.clone());
+                        }
+
+                        entry.insert(member);
+                    }
+                },
+                Entry::Vacant(entry) => {
+                    self.join_times.insert(member.host_key(), time::now_utc());
+                    entry.insert(member.clone());
+                    added.push(member);
+                },
+            }
+        }
+
+        let removed: Vec<Member> = current
+            .values()
+            .filter(|m| !kept.contains(&m.host_key()))
+            .cloned()
+            .collect();
+
+        for member in &removed {
+            current.remove(&member.host_key());
+            self.join_times.remove(&member.host_key());
+            self.last_probed.remove(&member.host_key());
+            self.last_acked.remove(&member.host_key());
+            self.history.remove(&member.host_key());
+        }
+
+        self.members.clear();
+        self.members.extend(current.into_iter().map(|(_, m)| m));
 
-        (new_nodes, changed_nodes)
+        (added, changed, updated, removed)
     }
 
-    pub fn hosts_for_indirect_ping(&self, host_count: usize, target: &SocketAddr) -> Vec<SocketAddr> {
+    pub fn record_ack(&mut self, remote_host: &SocketAddr) {
+        let now = time::now_utc();
+
+        for member in self.members.iter().filter(|m| m.remote_host() == Some(*remote_host)) {
+            self.last_acked.insert(member.host_key(), now);
+        }
+    }
+
+    pub fn hosts_for_indirect_ping(&mut self,
+                                   host_count: usize,
+                                   target: &SocketAddr,
+                                   rtts: Option<&HashMap<SocketAddr, Duration>>,
+                                   ack_window: Option<Duration>) -> Vec<SocketAddr> {
         let mut possible_members: Vec<_> = self.members
             .iter()
             .filter(|m|
                 m.state() == MemberState::Alive
                 && m.is_remote()
                 && m.remote_host() != Some(*target))
+            .collect();
+
+        if let Some(window) = ack_window {
+            let cutoff = time::now_utc() - window;
+            let last_acked = &self.last_acked;
+            let recently_acked: Vec<_> = possible_members
+                .iter()
+                .filter(|m| last_acked.get(&m.host_key()).map_or(false, |&t| t >= cutoff))
+                .cloned()
+                .collect();
+
+            if !recently_acked.is_empty() {
+                possible_members = recently_acked;
+            }
+        }
+
+        if possible_members.is_empty() {
+            return Vec::new();
+        }
+
+        possible_members.sort_by(|a, b| a.host_key().as_bytes().cmp(b.host_key().as_bytes()));
+
+        let start = self.relay_index % possible_members.len();
+        let count = cmp::min(host_count, possible_members.len());
+        self.relay_index = start + count;
+
+        let mut relays: Vec<_> = possible_members.iter()
+            .cycle()
+            .skip(start)
+            .take(possible_members.len())
             .map(|m| m.remote_host().unwrap())
             .collect();
 
-        rand::thread_rng().shuffle(&mut possible_members);
+        if let Some(rtts) = rtts {
+            relays.sort_by_key(|addr| rtts.get(addr).map_or(i64::MAX, |rtt| rtt.num_microseconds().unwrap_or(i64::MAX)));
+        }
 
-        possible_members.iter().take(host_count).cloned().collect()
+        relays.truncate(count);
+        relays
     }
 
     pub fn has_member(&self, remote_host: &SocketAddr) -> bool {
         self.members.iter().any(|ref m| m.remote_host() == Some(*remote_host))
     }
 
+    pub fn member_for(&self, remote_host: &SocketAddr) -> Option<Member> {
+        self.members
+            .iter()
+            .filter(|m| m.remote_host() == Some(*remote_host))
+            .max_by_key(|m| m.last_state_change().to_timespec())
+            .cloned()
+    }
+
+    pub fn member_by_host_key(&self, host_key: &Uuid) -> Option<Member> {
+        self.members.iter().find(|m| m.host_key() == *host_key).cloned()
+    }
+
+    pub fn host_key_for(&self, remote_host: &SocketAddr) -> Option<Uuid> {
+        self.members.iter().find(|m| m.remote_host() == Some(*remote_host)).map(|m| m.host_key())
+    }
+
+    pub fn has_host_key(&self, host_key: &Uuid) -> bool {
+        self.members.iter().any(|ref m| m.host_key() == *host_key)
+    }
+
     pub fn add_member(&mut self, member: Member) {
+        self.join_times.insert(member.host_key(), time::now_utc());
         self.members.push(member)
     }
 
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn count_in_state(&self, state: MemberState) -> usize {
+        self.members.iter().filter(|m| m.state() == state).count()
+    }
+
+}
+
+fn pick_weighted(weights: &[u64], mut point: u64) -> usize {
+    for (index, &weight) in weights.iter().enumerate() {
+        if point < weight {
+            return index;
+        }
+
+        point -= weight;
+    }
+
+    weights.len() - 1
+}
+
+fn record_transition(history: &mut HashMap<Uuid, VecDeque<(MemberState, Instant)>>, limit: usize, member: &Member) {
+    if limit == 0 {
+        return;
+    }
+
+    let transitions = history.entry(member.host_key()).or_insert_with(VecDeque::new);
+    transitions.push_back((member.state(), Instant::now()));
+    trim_transitions(transitions, limit);
+}
+
+fn trim_transitions(transitions: &mut VecDeque<(MemberState, Instant)>, limit: usize) {
+    while transitions.len() > limit {
+        transitions.pop_front();
+    }
+}
+
+fn compact_times(times: &mut HashMap<Uuid, time::Tm>, present: &HashSet<Uuid>, min_capacity: usize) {
+    times.retain(|host_key, _| present.contains(host_key));
+    times.shrink_to_fit();
+
+    let len = times.len();
+    times.reserve(min_capacity.saturating_sub(len));
+}
+
+pub fn scaled_suspicion_timeout(base: Duration, member_count: usize) -> Duration {
+    let scale = (member_count as f64).log10().max(1.0);
+
+    Duration::milliseconds((base.num_milliseconds() as f64 * scale) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::thread;
+    use std::usize;
+
+    use time;
+    use time::Duration;
+    use uuid::Uuid;
+
+    use member::{DefaultConflictResolver, LeftRejoinPolicy, Member, MemberState, StateChange};
+    use super::{pick_weighted, scaled_suspicion_timeout, MemberList};
+
+    #[test]
+    fn test_suspicion_timeout_grows_with_cluster_size() {
+        let base = Duration::seconds(3);
+
+        let small = scaled_suspicion_timeout(base, 3);
+        let large = scaled_suspicion_timeout(base, 100);
+
+        assert_eq!(small, base);
+        assert_eq!(large, Duration::seconds(6));
+        assert!(large > small);
+    }
+
+    #[test]
+    fn test_indirect_ping_relays_rotate_across_members() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let target: SocketAddr = FromStr::from_str("127.0.0.1:3000").unwrap();
+
+        for port in 3001..3011 {
+            let addr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+            members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+        }
+
+        let mut used = HashSet::new();
+        for _ in 0..4 {
+            let relays = members.hosts_for_indirect_ping(3, &target, None, None);
+            assert_eq!(relays.len(), 3);
+            used.extend(relays);
+        }
+
+        assert_eq!(used.len(), 10);
+    }
+
+    #[test]
+    fn test_new_member_is_not_suspected_within_grace_period() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+
+        let expired = vec![addr];
+        let (suspect, _, _) = members.time_out_nodes(expired.clone(), Duration::seconds(3), Duration::minutes(1),
+                                                     usize::MAX, &HashSet::new(), |_| true);
+        assert!(suspect.is_empty());
+
+        let (suspect, _, _) = members.time_out_nodes(expired, Duration::seconds(3), Duration::zero(),
+                                                     usize::MAX, &HashSet::new(), |_| true);
+        assert_eq!(suspect.len(), 1);
+    }
+
+    #[test]
+    fn test_transition_history_is_bounded() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let host_key = Uuid::new_v4();
+        members.add_member(Member::new(host_key, addr, 0, MemberState::Suspect));
+
+        members.mark_node_alive(&addr);
+        assert!(members.recent_transitions(&host_key).is_empty());
+
+        members.set_history_limit(2);
+        members.time_out_nodes(vec![addr], Duration::seconds(3), Duration::zero(), usize::MAX, &HashSet::new(), |_| true);
+        members.mark_node_alive(&addr);
+        members.time_out_nodes(vec![addr], Duration::seconds(3), Duration::zero(), usize::MAX, &HashSet::new(), |_| true);
+        members.time_out_nodes(vec![addr], Duration::seconds(-1), Duration::zero(), usize::MAX, &HashSet::new(), |_| true);
+
+        let states: Vec<_> = members.recent_transitions(&host_key).iter().map(|&(state, _)| state).collect();
+        assert_eq!(states, vec![MemberState::Suspect, MemberState::Down]);
+    }
+
+    #[test]
+    fn test_suspicions_beyond_the_limit_are_deferred() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3004)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        for addr in &addrs {
+            members.add_member(Member::new(Uuid::new_v4(), *addr, 0, MemberState::Alive));
+        }
+
+        let expired = addrs.clone();
+        let (suspect, _, deferred) = members.time_out_nodes(expired.clone(), Duration::seconds(3), Duration::zero(),
+                                                            2, &HashSet::new(), |_| true);
+        assert_eq!(suspect.len(), 2);
+        assert_eq!(deferred.len(), 1);
+
+        let (suspect, _, deferred) = members.time_out_nodes(deferred, Duration::seconds(3),
+                                                            Duration::zero(), 2, &HashSet::new(), |_| true);
+        assert_eq!(suspect.len(), 1);
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn test_escalated_hosts_do_not_count_against_the_limit_again() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3003)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        for addr in &addrs {
+            members.add_member(Member::new(Uuid::new_v4(), *addr, 0, MemberState::Alive));
+        }
+
+        let escalated = vec![addrs[0]].into_iter().collect();
+        let (suspect, _, deferred) = members.time_out_nodes(addrs.clone(), Duration::seconds(3), Duration::zero(),
+                                                            1, &escalated, |_| true);
+        assert_eq!(suspect.len(), 2);
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn test_suspicions_follow_the_order_of_expired_hosts() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3004)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        for addr in &addrs {
+            members.add_member(Member::new(Uuid::new_v4(), *addr, 0, MemberState::Alive));
+        }
+
+        let expired = vec![addrs[2], addrs[0], addrs[1]];
+        let (suspect, _, _) = members.time_out_nodes(expired.clone(), Duration::seconds(3), Duration::zero(),
+                                                     usize::MAX, &HashSet::new(), |_| true);
+        let suspected: Vec<SocketAddr> = suspect.iter().map(|m| m.remote_host().unwrap()).collect();
+
+        assert_eq!(suspected, expired);
+    }
+
+    #[test]
+    fn test_preallocated_member_list_absorbs_a_join_storm_without_growing() {
+        let mut members = MemberList::with_capacity(Member::myself(Uuid::new_v4()), 1001);
+        let capacity = members.members.capacity();
+
+        for i in 0..1000 {
+            let addr = FromStr::from_str(&format!("127.0.{}.{}:2552", i / 250, i % 250 + 1)).unwrap();
+            members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+        }
+
+        assert_eq!(members.members.capacity(), capacity);
+
+        let mut buffer = Vec::with_capacity(1001);
+        let buffer_capacity = buffer.capacity();
+        members.available_nodes_into(&mut buffer);
+        members.available_nodes_into(&mut buffer);
+
+        assert_eq!(buffer.len(), 1001);
+        assert_eq!(buffer.capacity(), buffer_capacity);
+    }
+
+    #[test]
+    fn test_compaction_reclaims_capacity_once_departed_members_are_reaped() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+
+        for i in 0..1000 {
+            let addr = FromStr::from_str(&format!("127.0.{}.{}:2552", i / 250, i % 250 + 1)).unwrap();
+            members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+        }
+
+        for member in members.members.iter_mut().filter(|m| m.is_remote()).take(990) {
+            member.set_state(MemberState::Left);
+        }
+
+        assert!(members.members.capacity() >= 1000);
+        assert!(!members.compact(0.25, 0));
+
+        assert!(members.reap(Duration::minutes(1)).is_empty());
+        assert_eq!(members.reap(Duration::zero()).len(), 990);
+        assert_eq!(members.len(), 11);
+
+        assert!(members.compact(0.25, 0));
+        assert!(members.members.capacity() < 100);
+        assert!(members.join_times.capacity() < 1000);
+
+        assert!(!members.compact(0.25, 0));
+    }
+
+    #[test]
+    fn test_compaction_keeps_the_expected_members_preallocation() {
+        let mut members = MemberList::with_capacity(Member::myself(Uuid::new_v4()), 1000);
+
+        assert!(!members.compact(0.25, 1000));
+        assert!(members.members.capacity() >= 1000);
+
+        for i in 0..2000 {
+            let addr = FromStr::from_str(&format!("127.0.{}.{}:2552", i / 250, i % 250 + 1)).unwrap();
+            members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Down));
+        }
+
+        assert_eq!(members.reap(Duration::zero()).len(), 2000);
+
+        assert!(members.compact(0.25, 1000));
+        assert!(members.members.capacity() >= 1000);
+        assert!(members.members.capacity() < 2000);
+    }
+
+    #[test]
+    fn test_join_times_expire_after_the_grace_period() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+
+        members.expire_join_times(Duration::minutes(1));
+        assert_eq!(members.join_times.len(), 1);
+
+        members.expire_join_times(Duration::zero());
+        assert!(members.join_times.is_empty());
+    }
+
+    #[test]
+    fn test_loading_members_diffs_against_the_current_set() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3004)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+        let keys: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        members.add_member(Member::new(keys[0], addrs[0], 1, MemberState::Suspect));
+        members.add_member(Member::new(keys[1], addrs[1], 0, MemberState::Alive));
+
+        let (added, changed, updated, removed) = members.load_members(vec![
+            Member::new(keys[0], addrs[0], 0, MemberState::Alive),
+            Member::new(keys[2], addrs[2], 0, MemberState::Alive),
+        ]);
+
+        assert_eq!(added.iter().map(|m| m.host_key()).collect::<Vec<_>>(), vec![keys[2]]);
+        assert!(changed.is_empty());
+        assert!(updated.is_empty());
+        assert_eq!(removed.iter().map(|m| m.host_key()).collect::<Vec<_>>(), vec![keys[1]]);
+        assert_eq!(members.member_by_host_key(&keys[0]).unwrap().state(), MemberState::Suspect);
+
+        let (added, changed, updated, removed) = members.load_members(vec![Member::new(keys[0], addrs[0], 2, MemberState::Alive)]);
+
+        assert!(added.is_empty());
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].0, MemberState::Suspect);
+        assert!(updated.is_empty());
+        assert_eq!(removed.iter().map(|m| m.host_key()).collect::<Vec<_>>(), vec![keys[2]]);
+        assert_eq!(members.len(), 2);
+        assert!(members.myself().is_myself());
+
+        let (_, changed, updated, _) = members.load_members(vec![Member::new(keys[0], addrs[0], 3, MemberState::Alive)]);
+
+        assert!(changed.is_empty());
+        assert_eq!(updated.iter().map(|m| m.incarnation()).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_pick_weighted() {
+        assert_eq!(pick_weighted(&[1, 10, 1], 0), 0);
+        assert_eq!(pick_weighted(&[1, 10, 1], 1), 1);
+        assert_eq!(pick_weighted(&[1, 10, 1], 10), 1);
+        assert_eq!(pick_weighted(&[1, 10, 1], 11), 2);
+    }
+
+    #[test]
+    fn test_round_robin_probes_every_member_once_per_round() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+
+        for port in 3001..3006 {
+            let addr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+            members.add_member(Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive));
+        }
+
+        let probed: HashSet<Uuid> = (0..5)
+            .filter_map(|_| members.next_random_member())
+            .map(|m| m.host_key())
+            .collect();
+
+        assert_eq!(probed.len(), 5);
+    }
+
+    #[test]
+    fn test_stale_members_are_probed_sooner() {
+        let mut stale_picks = 0;
+
+        for _ in 0..200 {
+            let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+            members.set_weighted_selection(true);
+            let now = time::now_utc();
+            let mut stale_key = None;
+
+            for port in 3001..3006 {
+                let addr = FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+                let member = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+                let last_probed = if port == 3001 { now - Duration::seconds(10) } else { now };
+
+                if port == 3001 {
+                    stale_key = Some(member.host_key());
+                }
+
+                members.last_probed.insert(member.host_key(), last_probed);
+                members.add_member(member);
+            }
+
+            if members.next_random_member().map(|m| m.host_key()) == stale_key {
+                stale_picks += 1;
+            }
+        }
+
+        assert!(stale_picks > 100, "stale member picked {} out of 200 times", stale_picks);
+    }
+
+    #[test]
+    fn test_estimated_size_uses_largest_advertised_size() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let mut peer = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+
+        members.add_member(peer.clone());
+        assert_eq!(members.estimated_size(), 2);
+        assert_eq!(members.myself().cluster_size(), 2);
+
+        peer.set_cluster_size(10);
+        members.apply_state_changes(vec![StateChange::new(peer)], &addr, &DefaultConflictResolver, true, |_| true);
+        assert_eq!(members.estimated_size(), 10);
+    }
+
+    #[test]
+    fn test_rejoin_after_leaving_is_rejected_by_default() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 3, MemberState::Left);
+        members.add_member(peer.clone());
+
+        let rejoined = Member::new(peer.host_key(), addr, 0, MemberState::Alive);
+        let (new, changed, updated) = members.apply_state_changes(vec![StateChange::new(rejoined)], &addr,
+                                                                  &DefaultConflictResolver, true, |_| true);
+
+        assert!(new.is_empty() && changed.is_empty() && updated.is_empty());
+        assert_eq!(members.member_by_host_key(&peer.host_key()).unwrap().state(), MemberState::Left);
+    }
+
+    #[test]
+    fn test_rejoin_after_leaving_is_readmitted_as_new_member() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        members.set_left_rejoin_policy(LeftRejoinPolicy::Readmit);
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 3, MemberState::Left);
+        members.add_member(peer.clone());
+
+        for rumour in vec![Member::new(peer.host_key(), addr, 3, MemberState::Alive),
+                           Member::new(peer.host_key(), addr, 4, MemberState::Down)] {
+            let (new, changed, updated) = members.apply_state_changes(vec![StateChange::new(rumour)], &addr,
+                                                                      &DefaultConflictResolver, true, |_| true);
+            assert!(new.is_empty() && changed.is_empty() && updated.is_empty());
+        }
+
+        let rejoined = Member::new(peer.host_key(), addr, 4, MemberState::Alive);
+        let (new, changed, _) = members.apply_state_changes(vec![StateChange::new(rejoined)], &addr,
+                                                            &DefaultConflictResolver, true, |_| true);
+
+        assert_eq!(new.len(), 1);
+        assert!(changed.is_empty());
+        assert_eq!(new[0].incarnation(), 4);
+
+        let member = members.member_by_host_key(&peer.host_key()).unwrap();
+        assert_eq!(member.state(), MemberState::Alive);
+        assert_eq!(members.available_nodes().len(), 2);
+
+        let (new, changed, updated) = members.apply_state_changes(vec![StateChange::new(peer.clone())], &addr,
+                                                                  &DefaultConflictResolver, true, |_| true);
+        assert!(new.is_empty() && changed.is_empty() && updated.is_empty());
+        assert_eq!(members.member_by_host_key(&peer.host_key()).unwrap().state(), MemberState::Alive);
+    }
+
+    #[test]
+    fn test_stale_self_incarnation_is_bumped_above_gossip() {
+        let host_key = Uuid::new_v4();
+        let mut members = MemberList::new(Member::myself(host_key));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+
+        let remembered = Member::new(host_key, addr, 5, MemberState::Alive);
+        let (_, changed, updated) = members.apply_state_changes(vec![StateChange::new(remembered)], &addr,
+                                                                &DefaultConflictResolver, true, |_| true);
+
+        assert!(changed.is_empty());
+        assert_eq!(updated.len(), 1);
+        assert_eq!(members.myself().incarnation(), 6);
+
+        let suspected = Member::new(host_key, addr, 9, MemberState::Suspect);
+        let (_, changed, _) = members.apply_state_changes(vec![StateChange::new(suspected)], &addr,
+                                                          &DefaultConflictResolver, true, |_| true);
+
+        assert_eq!(changed.len(), 1);
+        assert_eq!(members.myself().incarnation(), 10);
+        assert_eq!(members.myself().state(), MemberState::Alive);
+    }
+
+    #[test]
+    fn test_clear_suspicion_only_affects_suspected_members() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let peer = Member::new(Uuid::new_v4(), addr, 0, MemberState::Suspect);
+
+        members.add_member(peer.clone());
+
+        assert!(members.clear_suspicion(&Uuid::new_v4()).is_none());
+        assert_eq!(members.clear_suspicion(&peer.host_key()).map(|m| m.state()), Some(MemberState::Alive));
+        assert!(members.clear_suspicion(&peer.host_key()).is_none());
+    }
+
+    #[test]
+    fn test_member_for_prefers_most_recently_changed_member() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addr = FromStr::from_str("127.0.0.1:3001").unwrap();
+        let stale = Member::new(Uuid::new_v4(), addr, 0, MemberState::Down);
+        thread::sleep(time::Duration::milliseconds(10).to_std().unwrap());
+        let current = Member::new(Uuid::new_v4(), addr, 0, MemberState::Alive);
+
+        members.add_member(current.clone());
+        members.add_member(stale);
+
+        assert_eq!(members.member_for(&addr).map(|m| m.host_key()), Some(current.host_key()));
+        assert!(members.member_for(&FromStr::from_str("127.0.0.1:3002").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_indirect_ping_prefers_low_rtt_relays() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3005)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        for addr in &addrs {
+            members.add_member(Member::new(Uuid::new_v4(), *addr, 0, MemberState::Alive));
+        }
+
+        let mut rtts = HashMap::new();
+        rtts.insert(addrs[2], Duration::milliseconds(5));
+        rtts.insert(addrs[3], Duration::milliseconds(1));
+
+        let relays = members.hosts_for_indirect_ping(2, &addrs[0], Some(&rtts), None);
+        assert_eq!(relays, vec![addrs[3], addrs[2]]);
+
+        let relays = members.hosts_for_indirect_ping(3, &addrs[0], Some(&rtts), None);
+        assert_eq!(relays, vec![addrs[3], addrs[2], addrs[1]]);
+    }
+
+    #[test]
+    fn test_indirect_ping_relays_are_alive_and_recently_acked() {
+        let mut members = MemberList::new(Member::myself(Uuid::new_v4()));
+        let addrs: Vec<SocketAddr> = (3001..3005)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+
+        members.add_member(Member::new(Uuid::new_v4(), addrs[1], 0, MemberState::Suspect));
+        members.add_member(Member::new(Uuid::new_v4(), addrs[2], 0, MemberState::Alive));
+        members.add_member(Member::new(Uuid::new_v4(), addrs[3], 0, MemberState::Alive));
+
+        let relays = members.hosts_for_indirect_ping(3, &addrs[0], None, Some(Duration::seconds(10)));
+        assert_eq!(relays.len(), 2);
+        assert!(!relays.contains(&addrs[1]));
+
+        members.record_ack(&addrs[1]);
+        members.record_ack(&addrs[3]);
+
+        let relays = members.hosts_for_indirect_ping(3, &addrs[0], None, Some(Duration::seconds(10)));
+        assert_eq!(relays, vec![addrs[3]]);
+    }
 }
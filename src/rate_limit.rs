@@ -0,0 +1,75 @@
+use std::time::Instant;
+
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: usize, burst: usize) -> TokenBucket {
+        TokenBucket {
+            bytes_per_sec: bytes_per_sec as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_consume(&mut self, size: usize) -> bool {
+        self.try_consume_at(size, Instant::now())
+    }
+
+    /// Charges `size` bytes even if that puts the bucket into debt, which
+    /// later sends then have to pay off.
+    pub fn consume(&mut self, size: usize) {
+        self.refill(Instant::now());
+        self.tokens -= size as f64;
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        self.tokens = (self.tokens + elapsed_secs * self.bytes_per_sec).min(self.burst);
+        self.last_refill = now;
+    }
+
+    fn try_consume_at(&mut self, size: usize, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens >= size as f64 {
+            self.tokens -= size as f64;
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::TokenBucket;
+
+    #[test]
+    fn test_outbound_rate_stays_under_the_cap() {
+        let mut bucket = TokenBucket::new(10000, 1500);
+        let start = Instant::now();
+        bucket.last_refill = start;
+
+        let mut sent = 0;
+
+        for millis in 0..10000 {
+            if bucket.try_consume_at(1000, start + Duration::from_millis(millis)) {
+                sent += 1000;
+            }
+        }
+
+        assert!(sent <= 10000 * 10 + 1500, "sent {} bytes in 10 seconds", sent);
+        assert!(sent >= 10000 * 9, "sent only {} bytes in 10 seconds", sent);
+    }
+}
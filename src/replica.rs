@@ -0,0 +1,152 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::thread;
+
+use rustc_serialize::json;
+
+use ClusterEvent;
+
+pub struct Replica {
+    pub events: Receiver<ClusterEvent>,
+    local_addr: SocketAddr,
+}
+
+/// Forwards events to a collector from a dedicated thread, so a slow or
+/// unreachable collector never blocks the event loop.
+pub struct EventForwarder {
+    addr: SocketAddr,
+    tx: SyncSender<ClusterEvent>,
+}
+
+const FORWARDER_CAPACITY: usize = 1024;
+
+impl Replica {
+    pub fn listen(addr: SocketAddr) -> io::Result<Replica> {
+        let listener = try!(TcpListener::bind(&addr));
+        let local_addr = try!(listener.local_addr());
+        let (event_tx, event_rx) = channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let event_tx = event_tx.clone();
+                        thread::spawn(move || replay_events(stream, event_tx));
+                    },
+                    Err(e) => println!("WARNING: Could not accept replica connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Replica { events: event_rx, local_addr: local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl EventForwarder {
+    pub fn spawn(addr: SocketAddr) -> EventForwarder {
+        let (tx, rx) = sync_channel(FORWARDER_CAPACITY);
+
+        thread::spawn(move || {
+            let stream = match TcpStream::connect(&addr) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("ERROR: Could not connect to event collector {}: {}", addr, e);
+                    return;
+                },
+            };
+
+            if let Err(e) = write_events(stream, rx) {
+                println!("WARNING: Stopped forwarding events to {}: {}", addr, e);
+            }
+        });
+
+        EventForwarder { addr: addr, tx: tx }
+    }
+
+    /// Queues `event` for the collector. Returns false once the forwarding
+    /// thread has given up on the collector.
+    pub fn forward(&self, event: &ClusterEvent) -> bool {
+        match self.tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                println!("WARNING: Event collector {} is falling behind, dropping an event", self.addr);
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+fn write_events(mut stream: TcpStream, rx: Receiver<ClusterEvent>) -> io::Result<()> {
+    for event in rx {
+        let encoded = json::encode(&event).unwrap();
+
+        try!(stream.write_all(encoded.as_bytes()));
+        try!(stream.write_all(b"\n"));
+    }
+
+    Ok(())
+}
+
+fn replay_events(stream: TcpStream, event_tx: Sender<ClusterEvent>) {
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+
+        match json::decode(&line) {
+            Ok(event) => if event_tx.send(event).is_err() { return; },
+            Err(e) => println!("WARNING: Could not decode forwarded event: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use member::Member;
+    use MemberEvent;
+    use super::{EventForwarder, Replica};
+
+    #[test]
+    fn test_forwarded_events_reach_the_replica() {
+        let replica = Replica::listen(FromStr::from_str("127.0.0.1:0").unwrap()).unwrap();
+        let forwarder = EventForwarder::spawn(replica.local_addr());
+        let myself = Member::myself(Uuid::new_v4());
+
+        assert!(forwarder.forward(&(vec![myself.clone()], MemberEvent::Joined(myself.clone()))));
+
+        let (members, event) = replica.events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(members, vec![myself]);
+        assert!(event.member().is_myself());
+    }
+
+    #[test]
+    fn test_unreachable_collector_does_not_block_forwarding() {
+        let closed: SocketAddr = {
+            let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let forwarder = EventForwarder::spawn(closed);
+        let myself = Member::myself(Uuid::new_v4());
+
+        for _ in 0..10 {
+            forwarder.forward(&(vec![myself.clone()], MemberEvent::Joined(myself.clone())));
+        }
+
+        ::std::thread::sleep(Duration::from_millis(500));
+        assert!(!forwarder.forward(&(vec![myself.clone()], MemberEvent::Joined(myself))));
+    }
+}
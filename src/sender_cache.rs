@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use uuid::Uuid;
+
+pub struct SenderCache {
+    entries: HashMap<SocketAddr, (Uuid, u64)>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl SenderCache {
+    pub fn new(capacity: usize) -> SenderCache {
+        SenderCache {
+            entries: HashMap::with_capacity(capacity),
+            capacity: capacity,
+            tick: 0,
+        }
+    }
+
+    pub fn contains(&mut self, addr: &SocketAddr, host_key: &Uuid) -> bool {
+        self.tick += 1;
+
+        match self.entries.get_mut(addr) {
+            Some(&mut (ref cached, ref mut last_used)) if cached == host_key => {
+                *last_used = self.tick;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn insert(&mut self, addr: SocketAddr, host_key: Uuid) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&addr) {
+            let oldest = self.entries.iter().min_by_key(|&(_, &(_, last_used))| last_used).map(|(&addr, _)| addr);
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.tick += 1;
+        self.entries.insert(addr, (host_key, self.tick));
+    }
+
+    pub fn invalidate(&mut self, host_key: &Uuid) {
+        self.entries.retain(|_, &mut (ref cached, _)| cached != host_key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use uuid::Uuid;
+
+    use super::SenderCache;
+
+    #[test]
+    fn test_least_recently_used_sender_is_evicted() {
+        let mut cache = SenderCache::new(2);
+        let addrs: Vec<SocketAddr> = (3001..3004)
+            .map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+            .collect();
+        let keys: Vec<_> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        cache.insert(addrs[0], keys[0]);
+        cache.insert(addrs[1], keys[1]);
+        assert!(cache.contains(&addrs[0], &keys[0]));
+
+        cache.insert(addrs[2], keys[2]);
+        assert!(cache.contains(&addrs[0], &keys[0]));
+        assert!(!cache.contains(&addrs[1], &keys[1]));
+        assert!(cache.contains(&addrs[2], &keys[2]));
+
+        assert!(!cache.contains(&addrs[2], &keys[0]));
+
+        cache.invalidate(&keys[2]);
+        assert!(!cache.contains(&addrs[2], &keys[2]));
+    }
+}
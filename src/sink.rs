@@ -0,0 +1,49 @@
+use std::sync::mpsc::Sender;
+
+use ClusterEvent;
+
+/// Receives every member event on the event loop thread, so implementations
+/// must return quickly.
+pub trait EventSink: Send {
+    fn on_event(&mut self, event: &ClusterEvent);
+}
+
+pub struct ChannelSink {
+    tx: Sender<ClusterEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: Sender<ClusterEvent>) -> ChannelSink {
+        ChannelSink { tx: tx }
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn on_event(&mut self, event: &ClusterEvent) {
+        self.tx.send(event.clone()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+
+    use uuid::Uuid;
+
+    use member::Member;
+    use MemberEvent;
+    use super::{ChannelSink, EventSink};
+
+    #[test]
+    fn test_channel_sink_forwards_events() {
+        let (tx, rx) = channel();
+        let mut sink = ChannelSink::new(tx);
+        let myself = Member::myself(Uuid::new_v4());
+
+        sink.on_event(&(vec![myself.clone()], MemberEvent::Joined(myself.clone())));
+
+        let (members, event) = rx.try_recv().unwrap();
+        assert_eq!(members, vec![myself]);
+        assert!(event.member().is_myself());
+    }
+}
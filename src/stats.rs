@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    DecodeFailure,
+    Oversized,
+    ClusterKeyMismatch,
+    ChecksumFailure,
+    SelfAddressed,
+    Chaos,
+    IdentityRejected,
+}
+
+impl DropReason {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            DropReason::DecodeFailure => "decode_failure",
+            DropReason::Oversized => "oversized",
+            DropReason::ClusterKeyMismatch => "cluster_key_mismatch",
+            DropReason::ChecksumFailure => "checksum_failure",
+            DropReason::SelfAddressed => "self_addressed",
+            DropReason::Chaos => "chaos",
+            DropReason::IdentityRejected => "identity_rejected",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterStats {
+    /// Number of members, including this node, currently considered alive
+    pub alive_members: usize,
+    /// Number of members currently suspected of being down
+    pub suspect_members: usize,
+    /// Number of members that have been declared down
+    pub down_members: usize,
+    /// Number of members that have left the cluster
+    pub left_members: usize,
+    /// Number of state changes still waiting to be gossiped to the cluster
+    pub outstanding_state_changes: usize,
+    /// Number of pings that have been sent but not yet acknowledged or timed out
+    pub pending_responses: usize,
+    /// Interval between protocol periods currently used by this node
+    pub ping_interval: Duration,
+    /// Total number of datagrams sent since the node started
+    pub packets_sent: u64,
+    /// Total number of datagrams received since the node started
+    pub packets_received: u64,
+    /// Total number of bytes sent since the node started
+    pub bytes_sent: u64,
+    /// Total number of bytes received since the node started
+    pub bytes_received: u64,
+    /// Total number of received datagrams that were discarded without being processed
+    pub packets_dropped: u64,
+    /// Total number of datagrams deliberately dropped by chaos testing
+    pub chaos_dropped: u64,
+    /// Total number of received datagrams discarded because of a checksum mismatch
+    pub checksum_failures: u64,
+    /// Total number of member identities rejected because their signature could not be verified
+    pub identity_rejections: u64,
+    /// Total number of internal requests dropped because the event loop channel was full
+    pub internal_requests_dropped: u64,
+    /// Wire protocol version spoken by this node
+    pub protocol_version: u8,
+    /// Number of received datagrams, or members carried in them, discarded
+    /// for each reason
+    pub drop_counts: HashMap<DropReason, u64>,
+    /// Total number of sends deferred or dropped by the outbound rate cap
+    pub rate_limited_sends: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PacketCounters {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_dropped: u64,
+    pub chaos_dropped: u64,
+    pub checksum_failures: u64,
+    pub identity_rejections: u64,
+    pub internal_requests_dropped: u64,
+    pub drop_counts: HashMap<DropReason, u64>,
+    pub rate_limited_sends: u64,
+}
+
+impl PacketCounters {
+    pub fn record_sent(&mut self, size: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += size as u64;
+    }
+
+    pub fn record_received(&mut self, size: usize) {
+        self.packets_received += 1;
+        self.bytes_received += size as u64;
+    }
+
+    pub fn record_dropped(&mut self, reason: DropReason) {
+        self.packets_dropped += 1;
+        self.record_drop_reason(reason);
+    }
+
+    pub fn record_chaos_dropped(&mut self) {
+        self.chaos_dropped += 1;
+        self.record_drop_reason(DropReason::Chaos);
+    }
+
+    pub fn record_checksum_failure(&mut self) {
+        self.checksum_failures += 1;
+        self.record_drop_reason(DropReason::ChecksumFailure);
+    }
+
+    fn record_drop_reason(&mut self, reason: DropReason) {
+        *self.drop_counts.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn record_identity_rejection(&mut self) {
+        self.identity_rejections += 1;
+        self.record_drop_reason(DropReason::IdentityRejected);
+    }
+
+    pub fn record_internal_request_dropped(&mut self) {
+        self.internal_requests_dropped += 1;
+    }
+
+    pub fn record_rate_limited(&mut self) {
+        self.rate_limited_sends += 1;
+    }
+}
+
+impl ClusterStats {
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        write_metric_header(&mut out, "swim_members", "Number of known members by state", "gauge");
+        for &(state, count) in &[("alive", self.alive_members),
+                                 ("suspect", self.suspect_members),
+                                 ("down", self.down_members),
+                                 ("left", self.left_members)] {
+            writeln!(out, "swim_members{{state=\"{}\"}} {}", state, count).unwrap();
+        }
+
+        write_metric(&mut out, "swim_protocol_version",
+                     "Wire protocol version spoken by this node", "gauge",
+                     self.protocol_version as u64);
+        write_metric(&mut out, "swim_outstanding_state_changes",
+                     "Number of state changes waiting to be gossiped", "gauge",
+                     self.outstanding_state_changes as u64);
+        write_metric(&mut out, "swim_pending_responses",
+                     "Number of pings waiting for an acknowledgement", "gauge",
+                     self.pending_responses as u64);
+        write_metric(&mut out, "swim_packets_sent_total",
+                     "Total number of datagrams sent", "counter",
+                     self.packets_sent);
+        write_metric(&mut out, "swim_packets_received_total",
+                     "Total number of datagrams received", "counter",
+                     self.packets_received);
+        write_metric(&mut out, "swim_bytes_sent_total",
+                     "Total number of bytes sent", "counter",
+                     self.bytes_sent);
+        write_metric(&mut out, "swim_bytes_received_total",
+                     "Total number of bytes received", "counter",
+                     self.bytes_received);
+        write_metric(&mut out, "swim_packets_dropped_total",
+                     "Total number of received datagrams that were discarded", "counter",
+                     self.packets_dropped);
+        write_metric(&mut out, "swim_chaos_dropped_total",
+                     "Total number of datagrams dropped by chaos testing", "counter",
+                     self.chaos_dropped);
+        write_metric(&mut out, "swim_checksum_failures_total",
+                     "Total number of received datagrams with an invalid checksum", "counter",
+                     self.checksum_failures);
+        write_metric(&mut out, "swim_identity_rejections_total",
+                     "Total number of member identities that failed verification", "counter",
+                     self.identity_rejections);
+        write_metric(&mut out, "swim_internal_requests_dropped_total",
+                     "Total number of internal requests dropped under backpressure", "counter",
+                     self.internal_requests_dropped);
+        write_metric(&mut out, "swim_rate_limited_sends_total",
+                     "Total number of sends deferred or dropped by the outbound rate cap", "counter",
+                     self.rate_limited_sends);
+
+        let mut drop_counts: Vec<_> = self.drop_counts
+            .iter()
+            .map(|(reason, &count)| (reason.label(), count))
+            .collect();
+        drop_counts.sort();
+
+        write_metric_header(&mut out, "swim_packets_dropped_by_reason_total",
+                            "Total number of received datagrams discarded, by reason", "counter");
+        for (reason, count) in drop_counts {
+            writeln!(out, "swim_packets_dropped_by_reason_total{{reason=\"{}\"}} {}", reason, count).unwrap();
+        }
+
+        out
+    }
+}
+
+fn write_metric_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str, value: u64) {
+    write_metric_header(out, name, help, kind);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
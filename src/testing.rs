@@ -0,0 +1,99 @@
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::thread;
+
+use time;
+use time::Duration;
+use uuid::Uuid;
+
+use {try_start_cluster, Cluster, ClusterConfig};
+
+pub struct TestCluster {
+    pub nodes: Vec<Cluster>,
+}
+
+pub struct TestClusterBuilder {
+    node_count: usize,
+    configure: Box<Fn(&mut ClusterConfig)>,
+}
+
+impl TestCluster {
+    pub fn builder() -> TestClusterBuilder {
+        TestClusterBuilder {
+            node_count: 3,
+            configure: Box::new(|_| {}),
+        }
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.nodes.iter().map(|n| n.local_addr()).collect()
+    }
+
+    pub fn wait_converged(&self, timeout: Duration) -> bool {
+        let deadline = time::now_utc() + timeout;
+        let expected = self.nodes.len();
+
+        loop {
+            if self.nodes.iter().all(|n| n.stats().alive_members == expected) {
+                return true;
+            }
+
+            if time::now_utc() > deadline {
+                return false;
+            }
+
+            thread::sleep(Duration::milliseconds(50).to_std().unwrap());
+        }
+    }
+}
+
+impl TestClusterBuilder {
+    pub fn nodes(mut self, node_count: usize) -> Self {
+        self.node_count = node_count;
+        self
+    }
+
+    pub fn configure<F>(mut self, configure: F) -> Self where F: Fn(&mut ClusterConfig) + 'static {
+        self.configure = Box::new(configure);
+        self
+    }
+
+    pub fn start(self) -> io::Result<TestCluster> {
+        let mut nodes = Vec::new();
+
+        for _ in 0..self.node_count {
+            let mut config = ClusterConfig {
+                listen_addr: FromStr::from_str("127.0.0.1:0").unwrap(),
+                ping_interval: Duration::milliseconds(100),
+                .. ClusterConfig::default()
+            };
+            (self.configure)(&mut config);
+
+            nodes.push(try!(try_start_cluster(Uuid::new_v4(), config)));
+        }
+
+        if let Some(seed) = nodes.first().map(|n| n.local_addr()) {
+            for node in nodes.iter().skip(1) {
+                node.add_seed_node(seed);
+            }
+        }
+
+        Ok(TestCluster { nodes: nodes })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::Duration;
+
+    use super::TestCluster;
+
+    #[test]
+    fn test_nodes_converge_over_loopback() {
+        let cluster = TestCluster::builder().nodes(3).start().unwrap();
+
+        assert_eq!(cluster.addrs().len(), 3);
+        assert!(cluster.wait_converged(Duration::seconds(10)));
+    }
+}
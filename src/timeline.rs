@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineEventKind {
+    PingSent,
+    PingRequestSent,
+    AckReceived,
+    SuspicionStarted,
+    SuspicionCleared,
+    MemberDown,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelineEntry {
+    /// Monotonic timestamp in nanoseconds, only meaningful relative to other entries
+    pub timestamp_ns: u64,
+    pub kind: TimelineEventKind,
+    pub addr: SocketAddr,
+}
+
+pub struct Timeline {
+    entries: VecDeque<TimelineEntry>,
+    capacity: usize,
+}
+
+impl Timeline {
+    pub fn new(capacity: usize) -> Timeline {
+        Timeline {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    pub fn record(&mut self, kind: TimelineEventKind, addr: SocketAddr) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(TimelineEntry {
+            timestamp_ns: time::precise_time_ns(),
+            kind: kind,
+            addr: addr,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<TimelineEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use super::{Timeline, TimelineEventKind};
+
+    #[test]
+    fn test_timeline_overwrites_oldest_entries() {
+        let addr: SocketAddr = FromStr::from_str("127.0.0.1:2552").unwrap();
+        let mut timeline = Timeline::new(2);
+
+        timeline.record(TimelineEventKind::PingSent, addr);
+        timeline.record(TimelineEventKind::AckReceived, addr);
+        timeline.record(TimelineEventKind::PingSent, addr);
+
+        let kinds: Vec<_> = timeline.entries().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![TimelineEventKind::AckReceived, TimelineEventKind::PingSent]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Ping,
+    Ack,
+    PingRequest,
+    AckHost,
+    PingRequestFailed,
+    MtuProbe,
+    MtuProbeAck,
+    Reject,
+    Sync,
+    SyncAck,
+}
+
+#[derive(Debug, Clone)]
+pub struct PacketTrace {
+    pub direction: PacketDirection,
+    pub addr: SocketAddr,
+    pub size: usize,
+    pub kind: RequestKind,
+    pub summary: MessageSummary,
+}
+
+/// Compact description of a protocol message for logging and tracing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSummary {
+    pub sender: Uuid,
+    pub kind: RequestKind,
+    pub sequence: Option<u64>,
+    pub state_changes: usize,
+}
+
+impl fmt::Display for MessageSummary {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let sequence = match self.sequence {
+            Some(sequence) => format!("sequence {}", sequence),
+            None => String::from("no sequence"),
+        };
+
+        write!(formatter, "{:?} from {} ({}, {} state changes)",
+               self.kind, self.sender.to_hyphenated_string(), sequence, self.state_changes)
+    }
+}
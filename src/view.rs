@@ -0,0 +1,99 @@
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+
+use time::Duration;
+use uuid::Uuid;
+
+use member::{Member, MemberState};
+
+/// Snapshots compare by membership, state and incarnation only; the ages
+/// are local observations and differ between nodes sharing the same view.
+#[derive(RustcEncodable, RustcDecodable, Clone)]
+pub struct ViewSnapshot {
+    members: Vec<(Uuid, MemberState, u64)>,
+    ages_ms: Vec<i64>,
+}
+
+impl ViewSnapshot {
+    pub fn new(members: &[Member]) -> ViewSnapshot {
+        let mut members: Vec<_> = members.iter().collect();
+        members.sort_by(|a, b| a.host_key().as_bytes().cmp(b.host_key().as_bytes()));
+
+        ViewSnapshot {
+            members: members.iter().map(|m| (m.host_key(), m.state(), m.incarnation())).collect(),
+            ages_ms: members.iter().map(|m| m.age().num_milliseconds()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// How long `host_key` had been in its current state when the snapshot
+    /// was taken.
+    pub fn age(&self, host_key: &Uuid) -> Option<Duration> {
+        self.members
+            .iter()
+            .position(|&(ref key, _, _)| key == host_key)
+            .map(|i| Duration::milliseconds(self.ages_ms[i]))
+    }
+}
+
+impl PartialEq for ViewSnapshot {
+    fn eq(&self, rhs: &ViewSnapshot) -> bool {
+        self.members == rhs.members
+    }
+}
+
+impl Eq for ViewSnapshot {}
+
+impl Debug for ViewSnapshot {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        try!(formatter.write_str("ViewSnapshot ["));
+
+        for (&(ref host_key, ref state, incarnation), age_ms) in self.members.iter().zip(&self.ages_ms) {
+            try!(formatter.write_fmt(format_args!("\n  {} {:?} {} ({} ms)",
+                                                  host_key.to_hyphenated_string(), state, incarnation, age_ms)));
+        }
+
+        formatter.write_str("\n]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use time::Duration;
+    use uuid::Uuid;
+
+    use member::{Member, MemberState};
+    use super::ViewSnapshot;
+
+    #[test]
+    fn test_snapshots_ignore_order_and_addresses() {
+        let host_key = Uuid::new_v4();
+        let other = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3001").unwrap(), 2, MemberState::Suspect);
+        let remote = Member::new(host_key, FromStr::from_str("127.0.0.1:3002").unwrap(), 0, MemberState::Alive);
+
+        let a = ViewSnapshot::new(&[Member::myself(host_key), other.clone()]);
+        let b = ViewSnapshot::new(&[other.clone(), remote]);
+
+        assert_eq!(a, b);
+        assert!(a != ViewSnapshot::new(&[Member::myself(host_key)]));
+    }
+
+    #[test]
+    fn test_snapshots_carry_member_ages() {
+        let member = Member::new(Uuid::new_v4(), FromStr::from_str("127.0.0.1:3001").unwrap(), 0, MemberState::Suspect);
+        let snapshot = ViewSnapshot::new(&[member.clone()]);
+
+        let age = snapshot.age(&member.host_key()).unwrap();
+        assert!(age >= Duration::zero() && age < Duration::seconds(60));
+        assert!(snapshot.age(&Uuid::new_v4()).is_none());
+    }
+}